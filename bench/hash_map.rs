@@ -0,0 +1,261 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compare the Robin Hood `HashMap` against the linear probing table it replaced
+
+#[no_std];
+
+extern mod core;
+
+use core::container::Container;
+use core::cmp::Eq;
+use core::hash::{Hash, State, HashMap};
+use core::fail::abort;
+use core::vec::Vec;
+use core::option::{Option, Some, None};
+use core::mem::replace;
+use core::io::stderr;
+use core::slice::slice_from;
+use core::time::{Time, monotonic};
+
+static BENCH_SIZE: uint = 100000;
+
+static INITIAL_CAPACITY: uint = 32;
+
+struct Bucket<K, V> {
+    hash: uint,
+    key: K,
+    value: V
+}
+
+enum SearchResult {
+    FoundEntry(uint), FoundHole(uint), TableFull
+}
+
+// The previous `HashMap`: linear probing over `Option<Bucket>` slots, re-inserting the rest of the
+// cluster on removal
+struct LinearMap<K, V> {
+    hasher: State,
+    resize_at: uint,
+    size: uint,
+    buckets: Vec<Option<Bucket<K, V>>>
+}
+
+fn empty_buckets<K, V>(capacity: uint) -> Vec<Option<Bucket<K, V>>> {
+    let mut xs = Vec::with_capacity(capacity);
+    let mut i = 0;
+    while i < capacity {
+        xs.push(None);
+        i += 1;
+    }
+    xs
+}
+
+impl<K: Hash + Eq, V> LinearMap<K, V> {
+    fn new() -> LinearMap<K, V> {
+        LinearMap { hasher: State::new(0, 0), resize_at: INITIAL_CAPACITY * 3 / 4, size: 0,
+                    buckets: empty_buckets(INITIAL_CAPACITY) }
+    }
+
+    fn bucket_for_key(&self, hash: uint, k: &K) -> SearchResult {
+        let len_buckets = self.buckets.len();
+        let start = hash % len_buckets;
+        let mut idx = start;
+        loop {
+            match self.buckets.as_slice()[idx] {
+                Some(ref bkt) if bkt.hash == hash && *k == bkt.key => return FoundEntry(idx),
+                None => return FoundHole(idx),
+                _ => ()
+            }
+            idx = (idx + 1) % len_buckets;
+            if idx == start {
+                return TableFull
+            }
+        }
+    }
+
+    fn insert_internal(&mut self, hash: uint, k: K, v: V) -> Option<V> {
+        match self.bucket_for_key(hash, &k) {
+            TableFull => abort(),
+            FoundHole(idx) => {
+                self.buckets.as_mut_slice()[idx] = Some(Bucket { hash: hash, key: k, value: v });
+                self.size += 1;
+                None
+            }
+            FoundEntry(idx) => {
+                match self.buckets.as_mut_slice()[idx] {
+                    Some(ref mut b) => Some(replace(&mut b.value, v)),
+                    None => abort()
+                }
+            }
+        }
+    }
+
+    fn resize(&mut self, new_capacity: uint) {
+        self.resize_at = new_capacity * 3 / 4;
+        let mut old_buckets = replace(&mut self.buckets, empty_buckets(new_capacity));
+        self.size = 0;
+        loop {
+            match old_buckets.pop() {
+                Some(Some(Bucket { hash: hash, key: key, value: value })) => {
+                    self.insert_internal(hash, key, value);
+                }
+                Some(None) => (),
+                None => break
+            }
+        }
+    }
+
+    fn swap(&mut self, k: K, v: V) -> Option<V> {
+        if self.size >= self.resize_at {
+            let new_capacity = self.buckets.len() * 2;
+            self.resize(new_capacity);
+        }
+        let hash = k.hash_with(&self.hasher) as uint;
+        self.insert_internal(hash, k, v)
+    }
+
+    fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
+        let hash = k.hash_with(&self.hasher) as uint;
+        match self.bucket_for_key(hash, k) {
+            FoundEntry(idx) => match self.buckets.as_slice()[idx] {
+                Some(ref bkt) => Some(&bkt.value),
+                None => abort()
+            },
+            TableFull | FoundHole(_) => None
+        }
+    }
+
+    fn pop(&mut self, k: &K) -> Option<V> {
+        let hash = k.hash_with(&self.hasher) as uint;
+        let mut idx = match self.bucket_for_key(hash, k) {
+            TableFull | FoundHole(_) => return None,
+            FoundEntry(idx) => idx
+        };
+        let len_buckets = self.buckets.len();
+        let value = self.buckets.as_mut_slice()[idx].take().map(|bucket| bucket.value);
+
+        // Re-insert the rest of the cluster, as it may lie on the probe path of the removed key
+        let size = self.size - 1;
+        idx = (idx + 1) % len_buckets;
+        loop {
+            match self.buckets.as_mut_slice()[idx].take() {
+                Some(Bucket { hash: hash, key: key, value: value }) => {
+                    self.insert_internal(hash, key, value);
+                }
+                None => break
+            }
+            idx = (idx + 1) % len_buckets;
+        }
+        self.size = size;
+        value
+    }
+}
+
+trait BenchMap {
+    fn insert(&mut self, k: uint, v: uint);
+    fn contains(&self, k: &uint) -> bool;
+    fn remove(&mut self, k: &uint) -> bool;
+}
+
+impl BenchMap for HashMap<uint, uint> {
+    fn insert(&mut self, k: uint, v: uint) { self.swap(k, v); }
+    fn contains(&self, k: &uint) -> bool { self.find(k).is_some() }
+    fn remove(&mut self, k: &uint) -> bool { self.pop(k).is_some() }
+}
+
+impl BenchMap for LinearMap<uint, uint> {
+    fn insert(&mut self, k: uint, v: uint) { self.swap(k, v); }
+    fn contains(&self, k: &uint) -> bool { self.find(k).is_some() }
+    fn remove(&mut self, k: &uint) -> bool { self.pop(k).is_some() }
+}
+
+fn elapsed(start: Time) -> u64 {
+    let start = start.to_timespec();
+    let end = monotonic().to_timespec();
+    ((end.tv_sec - start.tv_sec) * 1000000000 + (end.tv_nsec - start.tv_nsec)) as u64
+}
+
+fn report(map: &[u8], name: &[u8], start: Time, ops: uint) {
+    let mut n = elapsed(start) / ops as u64;
+    let mut buf = [0u8, ..20];
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = '0' as u8 + (n % 10) as u8;
+        n /= 10;
+        if n == 0 { break }
+    }
+    stderr().write(map);
+    stderr().write(bytes!(" "));
+    stderr().write(name);
+    stderr().write(bytes!(": "));
+    stderr().write(slice_from(buf, i));
+    stderr().write(bytes!(" ns/op\n"));
+}
+
+fn fill<M: BenchMap>(m: &mut M) {
+    let mut i = 0;
+    while i < BENCH_SIZE {
+        m.insert(i, i);
+        i += 1;
+    }
+}
+
+fn bench_insert<M: BenchMap>(map: &[u8], mut m: M) {
+    let start = monotonic();
+    fill(&mut m);
+    report(map, bytes!("insert"), start, BENCH_SIZE);
+}
+
+fn bench_find<M: BenchMap>(map: &[u8], mut m: M) {
+    fill(&mut m);
+
+    let start = monotonic();
+    let mut i = 0;
+    while i < BENCH_SIZE {
+        if !m.contains(&i) { abort() }
+        i += 1;
+    }
+    report(map, bytes!("find hit"), start, BENCH_SIZE);
+
+    let start = monotonic();
+    i = BENCH_SIZE;
+    while i < BENCH_SIZE * 2 {
+        if m.contains(&i) { abort() }
+        i += 1;
+    }
+    report(map, bytes!("find miss"), start, BENCH_SIZE);
+}
+
+fn bench_pop<M: BenchMap>(map: &[u8], mut m: M) {
+    fill(&mut m);
+
+    // Alternate removals and insertions to keep the table at a high load factor.
+    let start = monotonic();
+    let mut i = 0;
+    while i < BENCH_SIZE {
+        if !m.remove(&i) { abort() }
+        m.insert(i + BENCH_SIZE, i);
+        i += 1;
+    }
+    report(map, bytes!("pop/swap"), start, BENCH_SIZE);
+}
+
+#[start]
+fn main(_: int, _: **u8) -> int {
+    bench_insert(bytes!("robin hood"), HashMap::<uint, uint>::with_capacity_and_keys(0, 0, 0));
+    bench_insert(bytes!("linear probing"), LinearMap::<uint, uint>::new());
+    bench_find(bytes!("robin hood"), HashMap::<uint, uint>::with_capacity_and_keys(0, 0, 0));
+    bench_find(bytes!("linear probing"), LinearMap::<uint, uint>::new());
+    bench_pop(bytes!("robin hood"), HashMap::<uint, uint>::with_capacity_and_keys(0, 0, 0));
+    bench_pop(bytes!("linear probing"), LinearMap::<uint, uint>::new());
+    0
+}
@@ -67,6 +67,19 @@ pub struct pthread_mutexattr_t {
     priv size: u32
 }
 
+#[cfg(target_word_size = "32")]
+pub struct pthread_rwlock_t {
+    priv size: [u32, ..8]
+}
+#[cfg(target_word_size = "64")]
+pub struct pthread_rwlock_t {
+    priv size: [u64, ..7]
+}
+
+pub struct pthread_rwlockattr_t {
+    priv size: u64
+}
+
 pub struct pthread_cond_t {
     priv size: [u64, ..6]
 }
@@ -72,6 +72,14 @@ impl<T: Eq> Eq for ~T {
     fn ne(&self, other: &~T) -> bool { **self != **other }
 }
 
+/// Equivalence between a borrowed form and an owned type, allowing lookups in containers without
+/// constructing an owned key
+///
+/// Hashed containers require that equivalent values produce the same hash.
+pub trait Equiv<T> {
+    fn equiv(&self, other: &T) -> bool;
+}
+
 #[deriving(Eq, Ord, Clone)]
 pub enum Ordering {
     Less,
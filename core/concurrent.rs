@@ -18,15 +18,52 @@ use clone::Clone;
 use arc::Arc;
 use deque::Deque;
 use priority_queue::PriorityQueue;
-use mem::transmute;
-use thread::{Mutex, Cond, Timeout};
+use mem::{forget, move_val_init, size_of, transmute};
+use thread::{Mutex, RwLock, Cond, Timeout, NoTimeout};
 use cmp::{Eq, Ord};
+use iter::Iterator;
 use option::{Some, None, Option};
-use hash::{Hash, HashMap};
+use hash::{Hash, HashBytes, HashMap, Hasher, State, Occupied, Vacant, random_keys};
+use lru::LruCache;
 use vec::Vec;
+use slice::mut_iter;
 use kinds::Send;
+use ops::Drop;
 use kinds::marker::NoFreeze;
 use time::{Time, monotonic};
+use atomic::{atomic_load_acq, atomic_load_relaxed, atomic_store_rel, atomic_cxchg_relaxed};
+use atomic::{atomic_store_relaxed, atomic_cxchg, atomic_xadd, atomic_xsub};
+use atomic::{atomic_fence, atomic_fence_rel};
+use heap::{alloc, free};
+use ptr::{copy_nonoverlapping_memory, offset, read_ptr};
+
+// Return the absolute time on the monotonic clock after `reltime` has elapsed
+fn deadline(reltime: Time) -> Time {
+    let mut abstime = monotonic().to_timespec();
+    let reltime = reltime.to_timespec();
+    abstime.tv_sec += reltime.tv_sec;
+    abstime.tv_nsec += reltime.tv_nsec;
+    if abstime.tv_nsec >= 1000000000 {
+        abstime.tv_sec += 1;
+        abstime.tv_nsec -= 1000000000;
+    }
+    Time::from_timespec(abstime)
+}
+
+// Wake up `n` of the `waiting` threads blocked on `cond`. Called with the lock held.
+unsafe fn wake(cond: &mut Cond, waiting: uint, n: uint) {
+    if n >= waiting {
+        if waiting != 0 {
+            cond.broadcast()
+        }
+    } else {
+        let mut i = 0;
+        while i < n {
+            cond.signal();
+            i += 1;
+        }
+    }
+}
 
 trait GenericQueue<T>: Container {
     fn generic_push(&mut self, item: T);
@@ -47,6 +84,8 @@ struct QueueBox<T> {
     queue: T,
     mutex: Mutex,
     not_empty: Cond,
+    consumers: uint,
+    selectors: Vec<Waiter>,
     no_freeze: NoFreeze
 }
 
@@ -58,7 +97,7 @@ impl<A: Send, T: GenericQueue<A>> QueuePtr<T> {
     fn new(queue: T) -> QueuePtr<T> {
         unsafe {
             let b = QueueBox { queue: queue, mutex: Mutex::new(), not_empty: Cond::new(),
-                               no_freeze: NoFreeze };
+                               consumers: 0, selectors: Vec::new(), no_freeze: NoFreeze };
             QueuePtr { ptr: Arc::new_unchecked(b) }
         }
     }
@@ -68,7 +107,9 @@ impl<A: Send, T: GenericQueue<A>> QueuePtr<T> {
             let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
             let mut guard = ptr.mutex.lock_guard();
             while ptr.queue.is_empty() {
-                ptr.not_empty.wait_guard(&mut guard)
+                ptr.consumers += 1;
+                ptr.not_empty.wait_guard(&mut guard);
+                ptr.consumers -= 1;
             }
             ptr.queue.generic_pop().get()
         }
@@ -84,14 +125,14 @@ impl<A: Send, T: GenericQueue<A>> QueuePtr<T> {
 
     fn pop_timeout(&self, reltime: Time) -> Option<A> {
         unsafe {
-            let mut abstime = monotonic().to_timespec();
-            abstime.tv_sec += reltime.to_timespec().tv_sec;
-            abstime.tv_nsec += reltime.to_timespec().tv_nsec;
-
+            let abstime = deadline(reltime);
             let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
             let mut guard = ptr.mutex.lock_guard();
             while ptr.queue.is_empty() {
-                if ptr.not_empty.wait_until_guard(&mut guard, Time::from_timespec(abstime)) == Timeout {
+                ptr.consumers += 1;
+                let status = ptr.not_empty.wait_until_guard(&mut guard, abstime);
+                ptr.consumers -= 1;
+                if status == Timeout {
                     return None
                 }
             }
@@ -99,12 +140,91 @@ impl<A: Send, T: GenericQueue<A>> QueuePtr<T> {
         }
     }
 
+    fn drain_into(&self, out: &mut Vec<A>, max: uint) -> uint {
+        unsafe {
+            let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            let mut n = 0;
+            while n < max {
+                match ptr.queue.generic_pop() {
+                    Some(x) => out.push(x),
+                    None => break
+                }
+                n += 1;
+            }
+            n
+        }
+    }
+
+    fn pop_batch(&self, max: uint, reltime: Time) -> Vec<A> {
+        let mut out = Vec::new();
+        unsafe {
+            let abstime = deadline(reltime);
+            let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            while ptr.queue.is_empty() {
+                ptr.consumers += 1;
+                let status = ptr.not_empty.wait_until_guard(&mut guard, abstime);
+                ptr.consumers -= 1;
+                if status == Timeout {
+                    return out
+                }
+            }
+            while out.len() < max {
+                match ptr.queue.generic_pop() {
+                    Some(x) => out.push(x),
+                    None => break
+                }
+            }
+        }
+        out
+    }
+
     fn push(&self, item: A) {
         unsafe {
             let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
             let _guard = ptr.mutex.lock_guard();
             ptr.queue.generic_push(item);
-            ptr.not_empty.signal()
+            ptr.not_empty.signal();
+            notify_waiters(&ptr.selectors)
+        }
+    }
+
+    fn push_all<I: Iterator<A>>(&self, iter: I) {
+        unsafe {
+            let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            let mut n = 0;
+            for item in iter {
+                ptr.queue.generic_push(item);
+                n += 1;
+            }
+            if n != 0 {
+                wake(&mut ptr.not_empty, ptr.consumers, n);
+                notify_waiters(&ptr.selectors)
+            }
+        }
+    }
+}
+
+impl<T: Container> QueuePtr<T> {
+    fn register(&self, waiter: &Waiter) -> bool {
+        unsafe {
+            let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            if !ptr.queue.is_empty() {
+                return true
+            }
+            add_waiter(&mut ptr.selectors, waiter);
+            false
+        }
+    }
+
+    fn unregister(&self, waiter: &Waiter) {
+        unsafe {
+            let ptr: &mut QueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            remove_waiter(&mut ptr.selectors, waiter)
         }
     }
 }
@@ -146,6 +266,24 @@ impl<T: Send> Queue<T> {
     pub fn push(&self, item: T) {
         self.ptr.push(item)
     }
+
+    /// Push every value yielded by `iter` to the back of the queue, under a single lock
+    /// acquisition.
+    pub fn push_all<I: Iterator<T>>(&self, iter: I) {
+        self.ptr.push_all(iter)
+    }
+
+    /// Pop up to `max` values from the front of the queue into `out`, without blocking. Return the
+    /// number of values moved.
+    pub fn drain_into(&self, out: &mut Vec<T>, max: uint) -> uint {
+        self.ptr.drain_into(out, max)
+    }
+
+    /// Pop up to `max` values from the front of the queue, blocking until the queue is not empty
+    /// or the timeout expires. Return an empty vector on timeout.
+    pub fn pop_batch(&self, max: uint, reltime: Time) -> Vec<T> {
+        self.ptr.pop_batch(max, reltime)
+    }
 }
 
 impl<T> Clone for Queue<T> {
@@ -155,6 +293,16 @@ impl<T> Clone for Queue<T> {
     }
 }
 
+impl<T: Send> Selectable for Queue<T> {
+    fn register(&self, waiter: &Waiter) -> bool {
+        self.ptr.register(waiter)
+    }
+
+    fn unregister(&self, waiter: &Waiter) {
+        self.ptr.unregister(waiter)
+    }
+}
+
 /// An unbounded, blocking concurrent priority queue
 pub struct BlockingPriorityQueue<T> {
     priv ptr: QueuePtr<PriorityQueue<T>>
@@ -186,6 +334,23 @@ impl<T: Ord + Send> BlockingPriorityQueue<T> {
     pub fn push(&self, item: T) {
         self.ptr.push(item)
     }
+
+    /// Push every value yielded by `iter` into the queue, under a single lock acquisition.
+    pub fn push_all<I: Iterator<T>>(&self, iter: I) {
+        self.ptr.push_all(iter)
+    }
+
+    /// Pop up to `max` of the largest values from the queue into `out`, without blocking. Return
+    /// the number of values moved.
+    pub fn drain_into(&self, out: &mut Vec<T>, max: uint) -> uint {
+        self.ptr.drain_into(out, max)
+    }
+
+    /// Pop up to `max` of the largest values from the queue, blocking until the queue is not empty
+    /// or the timeout expires. Return an empty vector on timeout.
+    pub fn pop_batch(&self, max: uint, reltime: Time) -> Vec<T> {
+        self.ptr.pop_batch(max, reltime)
+    }
 }
 
 impl<T> Clone for BlockingPriorityQueue<T> {
@@ -195,15 +360,221 @@ impl<T> Clone for BlockingPriorityQueue<T> {
     }
 }
 
+impl<T: Ord + Send> Selectable for BlockingPriorityQueue<T> {
+    fn register(&self, waiter: &Waiter) -> bool {
+        self.ptr.register(waiter)
+    }
+
+    fn unregister(&self, waiter: &Waiter) {
+        self.ptr.unregister(waiter)
+    }
+}
+
+struct Delayed<T> {
+    deadline: Time,
+    sequence: u64,
+    item: T
+}
+
+impl<T> Eq for Delayed<T> {
+    fn eq(&self, other: &Delayed<T>) -> bool {
+        self.deadline == other.deadline && self.sequence == other.sequence
+    }
+}
+
+// Reversed, so the heap yields the earliest deadline first and equal deadlines in insertion order
+impl<T> Ord for Delayed<T> {
+    fn lt(&self, other: &Delayed<T>) -> bool {
+        other.deadline < self.deadline ||
+            (other.deadline == self.deadline && other.sequence < self.sequence)
+    }
+}
+
+struct DelayQueueBox<T> {
+    queue: PriorityQueue<Delayed<T>>,
+    sequence: u64,
+    mutex: Mutex,
+    cond: Cond,
+    no_freeze: NoFreeze
+}
+
+impl<T: Send> DelayQueueBox<T> {
+    // Called with the lock held
+    fn next_deadline(&self) -> Option<Time> {
+        match self.queue.top() {
+            Some(entry) => Some(entry.deadline),
+            None => None
+        }
+    }
+
+    // Called with the lock held
+    unsafe fn take(&mut self) -> T {
+        let item = self.queue.pop().get().item;
+        // Hand the next item over to another blocked consumer
+        if !self.queue.is_empty() {
+            self.cond.signal()
+        }
+        item
+    }
+}
+
+/// An unbounded, blocking concurrent queue of values which become available once their deadline
+/// on the monotonic clock has passed
+pub struct DelayQueue<T> {
+    priv ptr: Arc<DelayQueueBox<T>>
+}
+
+impl<T: Send> DelayQueue<T> {
+    /// Return a new `DelayQueue` instance.
+    pub fn new() -> DelayQueue<T> {
+        unsafe {
+            let b = DelayQueueBox { queue: PriorityQueue::new(), sequence: 0, mutex: Mutex::new(),
+                                    cond: Cond::new(), no_freeze: NoFreeze };
+            DelayQueue { ptr: Arc::new_unchecked(b) }
+        }
+    }
+
+    /// Pop the value with the earliest deadline, blocking until the queue is not empty and that
+    /// deadline has passed.
+    pub fn pop(&self) -> T {
+        unsafe {
+            let ptr: &mut DelayQueueBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            loop {
+                match ptr.next_deadline() {
+                    Some(due) => {
+                        if monotonic() >= due {
+                            return ptr.take()
+                        }
+                        ptr.cond.wait_until_guard(&mut guard, due);
+                    }
+                    None => ptr.cond.wait_guard(&mut guard)
+                }
+            }
+        }
+    }
+
+    /// Pop the value with the earliest deadline, or return `None` if the queue is empty or that
+    /// deadline has not passed yet.
+    pub fn try_pop(&self) -> Option<T> {
+        unsafe {
+            let ptr: &mut DelayQueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            match ptr.next_deadline() {
+                Some(due) if monotonic() >= due => Some(ptr.take()),
+                _ => None
+            }
+        }
+    }
+
+    /// Pop the value with the earliest deadline, blocking until the queue is not empty and that
+    /// deadline has passed, or the timeout expires. Return `None` on timeout.
+    pub fn pop_timeout(&self, reltime: Time) -> Option<T> {
+        unsafe {
+            let abstime = deadline(reltime);
+            let ptr: &mut DelayQueueBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            loop {
+                let now = monotonic();
+                let mut until = abstime;
+                match ptr.next_deadline() {
+                    Some(due) => {
+                        if now >= due {
+                            return Some(ptr.take())
+                        }
+                        if due < abstime {
+                            until = due
+                        }
+                    }
+                    None => ()
+                }
+                if now >= abstime {
+                    return None
+                }
+                ptr.cond.wait_until_guard(&mut guard, until);
+            }
+        }
+    }
+
+    /// Push a value which becomes available once the monotonic clock reaches `abstime`.
+    pub fn push(&self, item: T, abstime: Time) {
+        unsafe {
+            let ptr: &mut DelayQueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            let sequence = ptr.sequence;
+            ptr.sequence += 1;
+            ptr.queue.push(Delayed { deadline: abstime, sequence: sequence, item: item });
+            // A consumer may be sleeping until a later deadline
+            if ptr.queue.top().get().sequence == sequence {
+                ptr.cond.signal()
+            }
+        }
+    }
+
+    /// Push a value which becomes available once `reltime` has elapsed.
+    pub fn push_after(&self, item: T, reltime: Time) {
+        self.push(item, deadline(reltime))
+    }
+}
+
+impl<T> Clone for DelayQueue<T> {
+    /// Return a shallow copy of the queue
+    fn clone(&self) -> DelayQueue<T> {
+        DelayQueue { ptr: self.ptr.clone() }
+    }
+}
+
 struct BoundedQueueBox<T> {
     deque: T,
     mutex: Mutex,
     not_empty: Cond,
     not_full: Cond,
     maximum: uint,
+    closed: bool,
+    consumers: uint,
+    producers: uint,
+    selectors: Vec<Waiter>,
     no_freeze: NoFreeze
 }
 
+impl<A: Send, T: GenericQueue<A>> BoundedQueueBox<T> {
+    // Called with the lock held
+    unsafe fn pop(&mut self) -> Option<A> {
+        match self.deque.generic_pop() {
+            Some(x) => {
+                self.not_full.signal();
+                Some(x)
+            }
+            None => None
+        }
+    }
+
+    // Called with the lock held, once there is room for the item
+    unsafe fn push(&mut self, item: A) -> Option<A> {
+        if self.closed {
+            return Some(item)
+        }
+        self.deque.generic_push(item);
+        self.not_empty.signal();
+        notify_waiters(&self.selectors);
+        None
+    }
+
+    // Called with the lock held
+    unsafe fn pop_into(&mut self, out: &mut Vec<A>, max: uint) -> uint {
+        let mut n = 0;
+        while n < max {
+            match self.deque.generic_pop() {
+                Some(x) => out.push(x),
+                None => break
+            }
+            n += 1;
+        }
+        wake(&mut self.not_full, self.producers, n);
+        n
+    }
+}
+
 struct BoundedQueuePtr<T> {
     ptr: Arc<BoundedQueueBox<T>>
 }
@@ -212,22 +583,23 @@ impl<A: Send, T: GenericQueue<A>> BoundedQueuePtr<T> {
     fn new(maximum: uint, queue: T) -> BoundedQueuePtr<T> {
         unsafe {
             let b = BoundedQueueBox { deque: queue, mutex: Mutex::new(), not_empty: Cond::new(),
-                                      not_full: Cond::new(), maximum: maximum,
+                                      not_full: Cond::new(), maximum: maximum, closed: false,
+                                      consumers: 0, producers: 0, selectors: Vec::new(),
                                       no_freeze: NoFreeze };
             BoundedQueuePtr { ptr: Arc::new_unchecked(b) }
         }
     }
 
-    fn pop(&self) -> A {
+    fn pop(&self) -> Option<A> {
         unsafe {
             let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
             let mut guard = ptr.mutex.lock_guard();
-            while ptr.deque.is_empty() {
-                ptr.not_empty.wait_guard(&mut guard)
+            while ptr.deque.is_empty() && !ptr.closed {
+                ptr.consumers += 1;
+                ptr.not_empty.wait_guard(&mut guard);
+                ptr.consumers -= 1;
             }
-            let item = ptr.deque.generic_pop().get();
-            ptr.not_full.signal();
-            item
+            ptr.pop()
         }
     }
 
@@ -235,44 +607,105 @@ impl<A: Send, T: GenericQueue<A>> BoundedQueuePtr<T> {
         unsafe {
             let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
             let _guard = ptr.mutex.lock_guard();
-            match ptr.deque.generic_pop() {
-                Some(x) => {
-                    ptr.not_full.signal();
-                    Some(x)
+            ptr.pop()
+        }
+    }
+
+    fn pop_timeout(&self, reltime: Time) -> Option<A> {
+        unsafe {
+            let abstime = deadline(reltime);
+            let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            while ptr.deque.is_empty() && !ptr.closed {
+                ptr.consumers += 1;
+                let status = ptr.not_empty.wait_until_guard(&mut guard, abstime);
+                ptr.consumers -= 1;
+                if status == Timeout {
+                    return None
                 }
-                None => None
             }
+            ptr.pop()
         }
     }
 
-    fn pop_timeout(&self, reltime: Time) -> Option<A> {
+    fn drain_into(&self, out: &mut Vec<A>, max: uint) -> uint {
         unsafe {
-            let mut abstime = monotonic().to_timespec();
-            abstime.tv_sec += reltime.to_timespec().tv_sec;
-            abstime.tv_nsec += reltime.to_timespec().tv_nsec;
+            let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.pop_into(out, max)
+        }
+    }
 
+    fn pop_batch(&self, max: uint, reltime: Time) -> Vec<A> {
+        let mut out = Vec::new();
+        unsafe {
+            let abstime = deadline(reltime);
             let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
             let mut guard = ptr.mutex.lock_guard();
-            while ptr.deque.is_empty() {
-                if ptr.not_empty.wait_until_guard(&mut guard, Time::from_timespec(abstime)) == Timeout {
-                    return None
+            while ptr.deque.is_empty() && !ptr.closed {
+                ptr.consumers += 1;
+                let status = ptr.not_empty.wait_until_guard(&mut guard, abstime);
+                ptr.consumers -= 1;
+                if status == Timeout {
+                    return out
                 }
             }
-            let item = ptr.deque.generic_pop().get();
-            ptr.not_full.signal();
-            Some(item)
+            ptr.pop_into(&mut out, max);
         }
+        out
     }
 
-    fn push(&self, item: A) {
+    fn push(&self, item: A) -> Option<A> {
         unsafe {
             let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
             let mut guard = ptr.mutex.lock_guard();
-            while ptr.deque.len() == ptr.maximum {
-                ptr.not_full.wait_guard(&mut guard)
+            while ptr.deque.len() == ptr.maximum && !ptr.closed {
+                ptr.producers += 1;
+                ptr.not_full.wait_guard(&mut guard);
+                ptr.producers -= 1;
+            }
+            ptr.push(item)
+        }
+    }
+
+    fn push_all<I: Iterator<A>>(&self, iter: I) -> Option<(A, I)> {
+        unsafe {
+            let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            let mut iter = iter;
+            let mut n = 0;
+            let mut rest = None;
+            loop {
+                let item = match iter.next() {
+                    Some(item) => item,
+                    None => break
+                };
+                while ptr.deque.len() == ptr.maximum && !ptr.closed {
+                    // Hand the values pushed so far to the consumers before blocking
+                    if n != 0 {
+                        wake(&mut ptr.not_empty, ptr.consumers, n);
+                        notify_waiters(&ptr.selectors);
+                        n = 0;
+                    }
+                    ptr.producers += 1;
+                    ptr.not_full.wait_guard(&mut guard);
+                    ptr.producers -= 1;
+                }
+                if ptr.closed {
+                    rest = Some(item);
+                    break
+                }
+                ptr.deque.generic_push(item);
+                n += 1;
+            }
+            if n != 0 {
+                wake(&mut ptr.not_empty, ptr.consumers, n);
+                notify_waiters(&ptr.selectors)
+            }
+            match rest {
+                Some(item) => Some((item, iter)),
+                None => None
             }
-            ptr.deque.generic_push(item);
-            ptr.not_empty.signal()
         }
     }
 
@@ -283,29 +716,66 @@ impl<A: Send, T: GenericQueue<A>> BoundedQueuePtr<T> {
             if ptr.deque.len() == ptr.maximum {
                 Some(item)
             } else {
-                ptr.deque.generic_push(item);
-                ptr.not_empty.signal();
-                None
+                ptr.push(item)
             }
         }
     }
 
     fn push_timeout(&self, item: A, reltime: Time) -> Option<A> {
         unsafe {
-            let mut abstime = monotonic().to_timespec();
-            abstime.tv_sec += reltime.to_timespec().tv_sec;
-            abstime.tv_nsec += reltime.to_timespec().tv_nsec;
-
+            let abstime = deadline(reltime);
             let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
             let mut guard = ptr.mutex.lock_guard();
-            while ptr.deque.len() == ptr.maximum {
-                if ptr.not_full.wait_until_guard(&mut guard, Time::from_timespec(abstime)) == Timeout {
+            while ptr.deque.len() == ptr.maximum && !ptr.closed {
+                ptr.producers += 1;
+                let status = ptr.not_full.wait_until_guard(&mut guard, abstime);
+                ptr.producers -= 1;
+                if status == Timeout {
                     return Some(item)
                 }
             }
-            ptr.deque.generic_push(item);
-            ptr.not_empty.signal();
-            None
+            ptr.push(item)
+        }
+    }
+
+    fn close(&self) {
+        unsafe {
+            let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.closed = true;
+            ptr.not_empty.broadcast();
+            ptr.not_full.broadcast();
+            notify_waiters(&ptr.selectors)
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        unsafe {
+            let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.closed
+        }
+    }
+}
+
+impl<T: Container> BoundedQueuePtr<T> {
+    fn register(&self, waiter: &Waiter) -> bool {
+        unsafe {
+            let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            if !ptr.deque.is_empty() || ptr.closed {
+                return true
+            }
+            add_waiter(&mut ptr.selectors, waiter);
+            false
+        }
+    }
+
+    fn unregister(&self, waiter: &Waiter) {
+        unsafe {
+            let ptr: &mut BoundedQueueBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            remove_waiter(&mut ptr.selectors, waiter)
         }
     }
 }
@@ -327,8 +797,9 @@ impl<T: Send> BoundedQueue<T> {
         BoundedQueue { ptr: BoundedQueuePtr::new(maximum, Deque::new()) }
     }
 
-    /// Pop a value from the front of the queue, blocking until the queue is not empty.
-    pub fn pop(&self) -> T {
+    /// Pop a value from the front of the queue, blocking until the queue is not empty. Return
+    /// `None` once the queue is closed and empty.
+    pub fn pop(&self) -> Option<T> {
         self.ptr.pop()
     }
 
@@ -338,26 +809,59 @@ impl<T: Send> BoundedQueue<T> {
     }
 
     /// Pop a value from the front of the queue, blocking until the queue is not empty or the
-    /// timeout expires.
+    /// timeout expires. Return `None` on timeout, or once the queue is closed and empty.
     pub fn pop_timeout(&self, reltime: Time) -> Option<T> {
         self.ptr.pop_timeout(reltime)
     }
 
-    /// Push a value to the back of the queue, blocking until the queue is not full.
-    pub fn push(&self, item: T) {
+    /// Push a value to the back of the queue, blocking until the queue is not full. If the queue
+    /// is closed, return `Some(item)`.
+    pub fn push(&self, item: T) -> Option<T> {
         self.ptr.push(item)
     }
 
-    /// Push a value to the back of the queue, or return `Some(item)` if the queue is full.
+    /// Push a value to the back of the queue, or return `Some(item)` if the queue is full or
+    /// closed.
     pub fn try_push(&self, item: T) -> Option<T> {
         self.ptr.try_push(item)
     }
 
     /// Push a value to the back of the queue, blocking until the queue is not full or the timeout
-    /// expires. If the timeout expires, return `Some(item)`.
+    /// expires. If the timeout expires or the queue is closed, return `Some(item)`.
     pub fn push_timeout(&self, item: T, reltime: Time) -> Option<T> {
         self.ptr.push_timeout(item, reltime)
     }
+
+    /// Push every value yielded by `iter` to the back of the queue, blocking whenever the queue is
+    /// full. If the queue is closed, stop and return `Some((item, iter))` with the first value which
+    /// was not pushed and the iterator over the rest.
+    pub fn push_all<I: Iterator<T>>(&self, iter: I) -> Option<(T, I)> {
+        self.ptr.push_all(iter)
+    }
+
+    /// Pop up to `max` values from the front of the queue into `out`, without blocking. Return the
+    /// number of values moved.
+    pub fn drain_into(&self, out: &mut Vec<T>, max: uint) -> uint {
+        self.ptr.drain_into(out, max)
+    }
+
+    /// Pop up to `max` values from the front of the queue, blocking until the queue is not empty or
+    /// the timeout expires. Return an empty vector on timeout, or once the queue is closed and
+    /// empty.
+    pub fn pop_batch(&self, max: uint, reltime: Time) -> Vec<T> {
+        self.ptr.pop_batch(max, reltime)
+    }
+
+    /// Close the queue, waking up every blocked caller. Further pushes fail, while the remaining
+    /// values can still be popped.
+    pub fn close(&self) {
+        self.ptr.close()
+    }
+
+    /// Return `true` if the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.ptr.is_closed()
+    }
 }
 
 impl<T> Clone for BoundedQueue<T> {
@@ -367,6 +871,16 @@ impl<T> Clone for BoundedQueue<T> {
     }
 }
 
+impl<T: Send> Selectable for BoundedQueue<T> {
+    fn register(&self, waiter: &Waiter) -> bool {
+        self.ptr.register(waiter)
+    }
+
+    fn unregister(&self, waiter: &Waiter) {
+        self.ptr.unregister(waiter)
+    }
+}
+
 /// A bounded, blocking concurrent priority queue
 pub struct BoundedPriorityQueue<T> {
     priv ptr: BoundedQueuePtr<PriorityQueue<T>>
@@ -378,8 +892,9 @@ impl<T: Ord + Send> BoundedPriorityQueue<T> {
         BoundedPriorityQueue { ptr: BoundedQueuePtr::new(maximum, PriorityQueue::new()) }
     }
 
-    /// Pop the largest value from the queue, blocking until the queue is not empty
-    pub fn pop(&self) -> T {
+    /// Pop the largest value from the queue, blocking until the queue is not empty. Return `None`
+    /// once the queue is closed and empty.
+    pub fn pop(&self) -> Option<T> {
         self.ptr.pop()
     }
 
@@ -389,26 +904,58 @@ impl<T: Ord + Send> BoundedPriorityQueue<T> {
     }
 
     /// Pop the largest value from the queue, blocking until the queue is not empty or the timeout
-    /// expires.
+    /// expires. Return `None` on timeout, or once the queue is closed and empty.
     pub fn pop_timeout(&self, reltime: Time) -> Option<T> {
         self.ptr.pop_timeout(reltime)
     }
 
-    /// Push a value into the queue, blocking until the queue is not full.
-    pub fn push(&self, item: T) {
+    /// Push a value into the queue, blocking until the queue is not full. If the queue is closed,
+    /// return `Some(item)`.
+    pub fn push(&self, item: T) -> Option<T> {
         self.ptr.push(item)
     }
 
-    /// Push a value into the queue, or return `Some(item)` if the queue is full.
+    /// Push a value into the queue, or return `Some(item)` if the queue is full or closed.
     pub fn try_push(&self, item: T) -> Option<T> {
         self.ptr.try_push(item)
     }
 
     /// Push a value into the queue, blocking until the queue is not full or the timeout expires. If
-    /// the timeout expires, return `Some(item)`.
+    /// the timeout expires or the queue is closed, return `Some(item)`.
     pub fn push_timeout(&self, item: T, reltime: Time) -> Option<T> {
         self.ptr.push_timeout(item, reltime)
     }
+
+    /// Push every value yielded by `iter` into the queue, blocking whenever the queue is full. If
+    /// the queue is closed, stop and return `Some((item, iter))` with the first value which was not
+    /// pushed and the iterator over the rest.
+    pub fn push_all<I: Iterator<T>>(&self, iter: I) -> Option<(T, I)> {
+        self.ptr.push_all(iter)
+    }
+
+    /// Pop up to `max` of the largest values from the queue into `out`, without blocking. Return
+    /// the number of values moved.
+    pub fn drain_into(&self, out: &mut Vec<T>, max: uint) -> uint {
+        self.ptr.drain_into(out, max)
+    }
+
+    /// Pop up to `max` of the largest values from the queue, blocking until the queue is not empty
+    /// or the timeout expires. Return an empty vector on timeout, or once the queue is closed and
+    /// empty.
+    pub fn pop_batch(&self, max: uint, reltime: Time) -> Vec<T> {
+        self.ptr.pop_batch(max, reltime)
+    }
+
+    /// Close the queue, waking up every blocked caller. Further pushes fail, while the remaining
+    /// values can still be popped.
+    pub fn close(&self) {
+        self.ptr.close()
+    }
+
+    /// Return `true` if the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.ptr.is_closed()
+    }
 }
 
 impl<T> Clone for BoundedPriorityQueue<T> {
@@ -418,55 +965,1346 @@ impl<T> Clone for BoundedPriorityQueue<T> {
     }
 }
 
-struct LockedHashMap<K, V> {
-    map: HashMap<K, V>,
+impl<T: Ord + Send> Selectable for BoundedPriorityQueue<T> {
+    fn register(&self, waiter: &Waiter) -> bool {
+        self.ptr.register(waiter)
+    }
+
+    fn unregister(&self, waiter: &Waiter) {
+        self.ptr.unregister(waiter)
+    }
+}
+
+struct SelectBox {
     mutex: Mutex,
-    no_freeze: NoFreeze
+    cond: Cond,
+    ready: bool
 }
 
-impl<K: Hash + Eq, V> LockedHashMap<K, V> {
-    fn with_capacity_and_keys(k0: u64, k1: u64, capacity: uint) -> LockedHashMap<K, V> {
-        LockedHashMap {
-            map: HashMap::with_capacity_and_keys(k0, k1, capacity),
+/// A registration of a `Select` with one of the queues it waits on
+pub struct Waiter {
+    priv select: *mut SelectBox
+}
+
+fn add_waiter(selectors: &mut Vec<Waiter>, waiter: &Waiter) {
+    selectors.push(Waiter { select: waiter.select })
+}
+
+fn remove_waiter(selectors: &mut Vec<Waiter>, waiter: &Waiter) {
+    let mut i = 0;
+    while i < selectors.len() {
+        if selectors.as_slice()[i].select == waiter.select {
+            selectors.remove(i);
+            return
+        }
+        i += 1;
+    }
+}
+
+// Wake up every `Select` waiting on a queue. Called with the lock of the queue held.
+unsafe fn notify_waiters(selectors: &Vec<Waiter>) {
+    let mut i = 0;
+    while i < selectors.len() {
+        let select = &mut *selectors.as_slice()[i].select;
+        let _guard = select.mutex.lock_guard();
+        select.ready = true;
+        select.cond.signal();
+        i += 1;
+    }
+}
+
+/// A queue which can be waited on by a `Select`
+pub trait Selectable {
+    /// Register `waiter` to be woken up by the next push, unless the queue is not empty. Return
+    /// `true` if the queue is not empty, in which case `waiter` is not registered.
+    fn register(&self, waiter: &Waiter) -> bool;
+
+    /// Remove a `waiter` previously registered with `register`.
+    fn unregister(&self, waiter: &Waiter);
+}
+
+/// Wait on several queues at once, until one of them is not empty
+///
+/// The queues are added with `add`, which returns the index used to identify them. A waiting
+/// `Select` registers itself with every queue and blocks on its own condition variable, which is
+/// signalled by the next push to any of them.
+pub struct Select<'a> {
+    priv targets: Vec<&'a Selectable>,
+    priv inner: ~SelectBox
+}
+
+impl<'a> Select<'a> {
+    /// Return a new `Select` instance, without any queues.
+    pub fn new() -> Select<'a> {
+        Select {
+            targets: Vec::new(),
+            inner: ~SelectBox { mutex: Mutex::new(), cond: Cond::new(), ready: false }
+        }
+    }
+
+    /// Add a queue to wait on, and return its index.
+    pub fn add(&mut self, queue: &'a Selectable) -> uint {
+        self.targets.push(queue);
+        self.targets.len() - 1
+    }
+
+    fn wait_until(&mut self, abstime: Option<Time>) -> Option<uint> {
+        let waiter = Waiter { select: &mut *self.inner };
+        loop {
+            self.inner.ready = false;
+
+            // Registration happens under the lock of each queue, so a push after a queue was seen
+            // empty always reaches this `Select`.
+            let mut found = None;
+            let mut registered = 0;
+            while registered < self.targets.len() {
+                if self.targets.as_slice()[registered].register(&waiter) {
+                    found = Some(registered);
+                    break
+                }
+                registered += 1;
+            }
+
+            let mut status = NoTimeout;
+            if found.is_none() {
+                unsafe {
+                    let ptr: &mut SelectBox = transmute(&mut *self.inner);
+                    let mut guard = ptr.mutex.lock_guard();
+                    while !ptr.ready && status == NoTimeout {
+                        status = match abstime {
+                            Some(t) => ptr.cond.wait_until_guard(&mut guard, t),
+                            None => { ptr.cond.wait_guard(&mut guard); NoTimeout }
+                        };
+                    }
+                }
+            }
+
+            let mut i = 0;
+            while i < registered {
+                self.targets.as_slice()[i].unregister(&waiter);
+                i += 1;
+            }
+
+            if found.is_some() {
+                return found
+            }
+            if status == Timeout {
+                return None
+            }
+        }
+    }
+
+    /// Block until one of the queues is not empty, and return its index. If several queues are
+    /// not empty, the one added first is returned.
+    ///
+    /// Another consumer may pop the value before the caller does, so the queue should be accessed
+    /// with `try_pop`.
+    pub fn wait(&mut self) -> uint {
+        self.wait_until(None).get()
+    }
+
+    /// Block until one of the queues is not empty or the timeout expires, and return the index of
+    /// the queue. If several queues are not empty, the one added first is returned.
+    pub fn wait_timeout(&mut self, reltime: Time) -> Option<uint> {
+        self.wait_until(Some(deadline(reltime)))
+    }
+}
+
+struct ChannelBox<T> {
+    queue: Deque<T>,
+    maximum: Option<uint>,
+    senders: uint,
+    receiver: bool,
+    mutex: Mutex,
+    not_empty: Cond,
+    not_full: Cond,
+    no_freeze: NoFreeze
+}
+
+impl<T: Send> ChannelBox<T> {
+    fn is_full(&self) -> bool {
+        match self.maximum {
+            Some(maximum) => self.queue.len() >= maximum,
+            None => false
+        }
+    }
+
+    unsafe fn pop(&mut self) -> Option<T> {
+        let item = self.queue.pop_front();
+        if item.is_some() && self.maximum.is_some() {
+            self.not_full.signal()
+        }
+        item
+    }
+}
+
+fn new_channel<T: Send>(maximum: Option<uint>) -> (Sender<T>, Receiver<T>) {
+    let b = ChannelBox { queue: Deque::new(), maximum: maximum, senders: 1, receiver: true,
+                         mutex: Mutex::new(), not_empty: Cond::new(), not_full: Cond::new(),
+                         no_freeze: NoFreeze };
+    unsafe {
+        let ptr = Arc::new_unchecked(b);
+        (Sender { ptr: ptr.clone() }, Receiver { ptr: ptr })
+    }
+}
+
+/// Create an unbounded channel, returning the sending and receiving halves.
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    new_channel(None)
+}
+
+/// Create a bounded channel holding at most `maximum` values, returning the sending and receiving
+/// halves. Sending blocks while the channel is full. The maximum is at least 1, as there is no
+/// rendezvous between a sender and the receiver.
+pub fn sync_channel<T: Send>(maximum: uint) -> (Sender<T>, Receiver<T>) {
+    new_channel(Some(if maximum == 0 { 1 } else { maximum }))
+}
+
+/// The sending half of a channel
+///
+/// The sender can be cloned to obtain more producers. Once every sender is dropped, the receiver
+/// is woken up and `recv` returns `None` after draining the remaining values.
+pub struct Sender<T> {
+    priv ptr: Arc<ChannelBox<T>>
+}
+
+impl<T: Send> Sender<T> {
+    /// Send a value to the receiver, blocking until the channel is not full. If the receiver has
+    /// been dropped, return `Some(item)`.
+    pub fn send(&self, item: T) -> Option<T> {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            while ptr.receiver && ptr.is_full() {
+                ptr.not_full.wait_guard(&mut guard)
+            }
+            if !ptr.receiver {
+                return Some(item)
+            }
+            ptr.queue.push_back(item);
+            ptr.not_empty.signal();
+            None
+        }
+    }
+
+    /// Send a value to the receiver, or return `Some(item)` if the channel is full or the receiver
+    /// has been dropped.
+    pub fn try_send(&self, item: T) -> Option<T> {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            if !ptr.receiver || ptr.is_full() {
+                return Some(item)
+            }
+            ptr.queue.push_back(item);
+            ptr.not_empty.signal();
+            None
+        }
+    }
+
+    /// Return `true` if the receiver has been dropped.
+    pub fn is_disconnected(&self) -> bool {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            !ptr.receiver
+        }
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    /// Return another sender for the same channel
+    fn clone(&self) -> Sender<T> {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.senders += 1;
+        }
+        Sender { ptr: self.ptr.clone() }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.senders -= 1;
+            if ptr.senders == 0 {
+                ptr.not_empty.broadcast()
+            }
+        }
+    }
+}
+
+/// The receiving half of a channel
+///
+/// Once the receiver is dropped, blocked senders are woken up and sending fails.
+pub struct Receiver<T> {
+    priv ptr: Arc<ChannelBox<T>>
+}
+
+impl<T: Send> Receiver<T> {
+    /// Receive a value, blocking until one is available. Return `None` once the channel is empty
+    /// and every sender has been dropped.
+    pub fn recv(&self) -> Option<T> {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            while ptr.queue.is_empty() && ptr.senders != 0 {
+                ptr.not_empty.wait_guard(&mut guard)
+            }
+            ptr.pop()
+        }
+    }
+
+    /// Receive a value, or return `None` if the channel is empty.
+    pub fn try_recv(&self) -> Option<T> {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.pop()
+        }
+    }
+
+    /// Receive a value, blocking until one is available or the timeout expires. Return `None` on
+    /// timeout, or once the channel is empty and every sender has been dropped.
+    pub fn recv_timeout(&self, reltime: Time) -> Option<T> {
+        unsafe {
+            let abstime = deadline(reltime);
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            while ptr.queue.is_empty() && ptr.senders != 0 {
+                if ptr.not_empty.wait_until_guard(&mut guard, abstime) == Timeout {
+                    return None
+                }
+            }
+            ptr.pop()
+        }
+    }
+
+    /// Return `true` if every sender has been dropped. Values sent before may still be buffered.
+    pub fn is_disconnected(&self) -> bool {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.senders == 0
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let ptr: &mut ChannelBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.receiver = false;
+            ptr.not_full.broadcast()
+        }
+    }
+}
+
+struct PromiseBox<A> {
+    value: Option<A>,
+    promise: bool,
+    mutex: Mutex,
+    cond: Cond,
+    no_freeze: NoFreeze
+}
+
+/// Create a single-assignment slot, returning the writing half and the reading half.
+pub fn promise<A: Send>() -> (Promise<A>, Future<A>) {
+    let b = PromiseBox { value: None, promise: true, mutex: Mutex::new(), cond: Cond::new(),
+                         no_freeze: NoFreeze };
+    unsafe {
+        let ptr = Arc::new_unchecked(b);
+        (Promise { ptr: ptr.clone() }, Future { ptr: ptr })
+    }
+}
+
+/// The writing half of a single-assignment slot
+///
+/// Once the promise is set or dropped, the future is woken up.
+pub struct Promise<A> {
+    priv ptr: Arc<PromiseBox<A>>
+}
+
+impl<A: Send> Promise<A> {
+    /// Set the value of the future.
+    pub fn set(self, value: A) {
+        unsafe {
+            let ptr: &mut PromiseBox<A> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.value = Some(value)
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<A: Send> Drop for Promise<A> {
+    fn drop(&mut self) {
+        unsafe {
+            let ptr: &mut PromiseBox<A> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.promise = false;
+            ptr.cond.signal()
+        }
+    }
+}
+
+/// The reading half of a single-assignment slot
+pub struct Future<A> {
+    priv ptr: Arc<PromiseBox<A>>
+}
+
+impl<A: Send> Future<A> {
+    /// Take the value, blocking until the promise is set. Return `None` if the value has already
+    /// been taken, or if the promise was dropped without being set.
+    pub fn get(&self) -> Option<A> {
+        unsafe {
+            let ptr: &mut PromiseBox<A> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            while ptr.promise && ptr.value.is_none() {
+                ptr.cond.wait_guard(&mut guard)
+            }
+            ptr.value.take()
+        }
+    }
+
+    /// Take the value, or return `None` if the promise has not been set yet.
+    pub fn try_get(&self) -> Option<A> {
+        unsafe {
+            let ptr: &mut PromiseBox<A> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.value.take()
+        }
+    }
+
+    /// Take the value, blocking until the promise is set or the timeout expires. Return `None` on
+    /// timeout, if the value has already been taken, or if the promise was dropped without being
+    /// set.
+    pub fn get_timeout(&self, reltime: Time) -> Option<A> {
+        unsafe {
+            let abstime = deadline(reltime);
+            let ptr: &mut PromiseBox<A> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            while ptr.promise && ptr.value.is_none() {
+                if ptr.cond.wait_until_guard(&mut guard, abstime) == Timeout {
+                    break
+                }
+            }
+            ptr.value.take()
+        }
+    }
+}
+
+/// The result of receiving from a broadcast channel
+pub enum Received<T> {
+    /// The next message
+    Message(T),
+    /// The subscriber fell behind and this many messages were overwritten before it received
+    /// them. Receiving continues with the oldest message still buffered.
+    Lagged(u64),
+    /// No message is available yet
+    Empty,
+    /// Every publisher has been dropped and every buffered message has been received
+    Disconnected
+}
+
+struct BroadcastBox<T> {
+    buffer: Vec<T>,
+    capacity: uint,
+    next: u64,
+    publishers: uint,
+    mutex: Mutex,
+    not_empty: Cond,
+    no_freeze: NoFreeze
+}
+
+impl<T: Send + Clone> BroadcastBox<T> {
+    // Called with the lock held
+    fn publish(&mut self, item: T) {
+        if self.buffer.len() < self.capacity {
+            self.buffer.push(item)
+        } else {
+            let i = (self.next % self.capacity as u64) as uint;
+            self.buffer.as_mut_slice()[i] = item;
+        }
+        self.next += 1;
+    }
+
+    // Called with the lock held. Return `Empty` if the subscriber has to wait for a message.
+    fn receive(&self, cursor: &mut u64) -> Received<T> {
+        let capacity = self.capacity as u64;
+        let oldest = if self.next > capacity { self.next - capacity } else { 0 };
+        if *cursor < oldest {
+            let lag = oldest - *cursor;
+            *cursor = oldest;
+            Lagged(lag)
+        } else if *cursor < self.next {
+            let i = (*cursor % capacity) as uint;
+            *cursor += 1;
+            Message(self.buffer.as_slice()[i].clone())
+        } else if self.publishers == 0 {
+            Disconnected
+        } else {
+            Empty
+        }
+    }
+}
+
+/// Create a broadcast channel buffering the last `capacity` messages, returning the publishing
+/// half. The capacity is at least 1. Subscribers are obtained from `Publisher::subscribe`.
+pub fn broadcast<T: Send + Clone>(capacity: uint) -> Publisher<T> {
+    let capacity = if capacity == 0 { 1 } else { capacity };
+    let b = BroadcastBox { buffer: Vec::with_capacity(capacity), capacity: capacity, next: 0,
+                           publishers: 1, mutex: Mutex::new(), not_empty: Cond::new(),
+                           no_freeze: NoFreeze };
+    unsafe {
+        Publisher { ptr: Arc::new_unchecked(b) }
+    }
+}
+
+/// The publishing half of a broadcast channel
+///
+/// Publishing never blocks: once the buffer is full, the oldest message is overwritten and the
+/// subscribers which have not received it yet are told how far they lagged behind. The publisher
+/// can be cloned, and once every publisher is dropped the subscribers are woken up and receive
+/// `Disconnected` after draining the buffer.
+pub struct Publisher<T> {
+    priv ptr: Arc<BroadcastBox<T>>
+}
+
+impl<T: Send + Clone> Publisher<T> {
+    /// Publish a message to every subscriber.
+    pub fn send(&self, item: T) {
+        unsafe {
+            let ptr: &mut BroadcastBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.publish(item);
+            ptr.not_empty.broadcast()
+        }
+    }
+
+    /// Return a new subscriber, receiving every message published from now on.
+    pub fn subscribe(&self) -> Subscriber<T> {
+        unsafe {
+            let ptr: &mut BroadcastBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            Subscriber { ptr: self.ptr.clone(), cursor: ptr.next }
+        }
+    }
+}
+
+impl<T: Send + Clone> Clone for Publisher<T> {
+    /// Return another publisher for the same channel
+    fn clone(&self) -> Publisher<T> {
+        unsafe {
+            let ptr: &mut BroadcastBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.publishers += 1;
+        }
+        Publisher { ptr: self.ptr.clone() }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send + Clone> Drop for Publisher<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let ptr: &mut BroadcastBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.publishers -= 1;
+            if ptr.publishers == 0 {
+                ptr.not_empty.broadcast()
+            }
+        }
+    }
+}
+
+/// The receiving half of a broadcast channel, with its own position in the stream of messages
+pub struct Subscriber<T> {
+    priv ptr: Arc<BroadcastBox<T>>,
+    priv cursor: u64
+}
+
+impl<T: Send + Clone> Subscriber<T> {
+    /// Receive the next message, blocking until one is published. Never returns `Empty`.
+    pub fn recv(&mut self) -> Received<T> {
+        unsafe {
+            let ptr: &mut BroadcastBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            loop {
+                match ptr.receive(&mut self.cursor) {
+                    Empty => ptr.not_empty.wait_guard(&mut guard),
+                    result => return result
+                }
+            }
+        }
+    }
+
+    /// Receive the next message, or return `Empty` if none is available.
+    pub fn try_recv(&mut self) -> Received<T> {
+        unsafe {
+            let ptr: &mut BroadcastBox<T> = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.receive(&mut self.cursor)
+        }
+    }
+
+    /// Receive the next message, blocking until one is published or the timeout expires. Return
+    /// `Empty` on timeout.
+    pub fn recv_timeout(&mut self, reltime: Time) -> Received<T> {
+        unsafe {
+            let abstime = deadline(reltime);
+            let ptr: &mut BroadcastBox<T> = transmute(self.ptr.borrow());
+            let mut guard = ptr.mutex.lock_guard();
+            loop {
+                match ptr.receive(&mut self.cursor) {
+                    Empty => {
+                        if ptr.not_empty.wait_until_guard(&mut guard, abstime) == Timeout {
+                            return ptr.receive(&mut self.cursor)
+                        }
+                    }
+                    result => return result
+                }
+            }
+        }
+    }
+}
+
+impl<T: Send + Clone> Clone for Subscriber<T> {
+    /// Return another subscriber at the same position in the stream of messages
+    fn clone(&self) -> Subscriber<T> {
+        Subscriber { ptr: self.ptr.clone(), cursor: self.cursor }
+    }
+}
+
+// A slot of a `RingQueue`. The sequence number tracks whose turn it is: the slot is free for the
+// push at position `pos` when it is equal to `pos`, and holds the value pushed at `pos` when it is
+// equal to `pos + 1`.
+struct Slot<T> {
+    sequence: uint,
+    value: Option<T>
+}
+
+static CACHE_LINE: uint = 64;
+
+struct RingQueueBox<T> {
+    buffer: Vec<Slot<T>>,
+    mask: uint,
+    pad0: [u8, ..CACHE_LINE],
+    enqueue_pos: uint,
+    pad1: [u8, ..CACHE_LINE],
+    dequeue_pos: uint,
+    pad2: [u8, ..CACHE_LINE],
+    mutex: Mutex,
+    not_empty: Cond,
+    not_full: Cond,
+    waiting_consumers: uint,
+    waiting_producers: uint,
+    no_freeze: NoFreeze
+}
+
+impl<T: Send> RingQueueBox<T> {
+    fn try_push(&mut self, item: T) -> Option<T> {
+        unsafe {
+            let mut pos = atomic_load_relaxed(&self.enqueue_pos);
+            loop {
+                let slot = &mut self.buffer.as_mut_slice()[pos & self.mask];
+                let diff = atomic_load_acq(&slot.sequence) as int - pos as int;
+                if diff == 0 {
+                    let old = atomic_cxchg_relaxed(&mut self.enqueue_pos, pos, pos + 1);
+                    if old == pos {
+                        slot.value = Some(item);
+                        atomic_store_rel(&mut slot.sequence, pos + 1);
+                        return None
+                    }
+                    pos = old;
+                } else if diff < 0 {
+                    return Some(item)
+                } else {
+                    pos = atomic_load_relaxed(&self.enqueue_pos);
+                }
+            }
+        }
+    }
+
+    fn try_pop(&mut self) -> Option<T> {
+        unsafe {
+            let mut pos = atomic_load_relaxed(&self.dequeue_pos);
+            loop {
+                let slot = &mut self.buffer.as_mut_slice()[pos & self.mask];
+                let diff = atomic_load_acq(&slot.sequence) as int - (pos + 1) as int;
+                if diff == 0 {
+                    let old = atomic_cxchg_relaxed(&mut self.dequeue_pos, pos, pos + 1);
+                    if old == pos {
+                        let item = slot.value.take();
+                        atomic_store_rel(&mut slot.sequence, pos + self.mask + 1);
+                        return item
+                    }
+                    pos = old;
+                } else if diff < 0 {
+                    return None
+                } else {
+                    pos = atomic_load_relaxed(&self.dequeue_pos);
+                }
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        unsafe {
+            let pos = atomic_load_relaxed(&self.enqueue_pos);
+            let slot = &self.buffer.as_slice()[pos & self.mask];
+            (atomic_load_acq(&slot.sequence) as int - pos as int) < 0
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        unsafe {
+            let pos = atomic_load_relaxed(&self.dequeue_pos);
+            let slot = &self.buffer.as_slice()[pos & self.mask];
+            (atomic_load_acq(&slot.sequence) as int - (pos + 1) as int) < 0
+        }
+    }
+
+    // Signal `cond` only if a thread has announced itself as parked on it. The fence pairs with
+    // the one in `push` and `pop`: either the parked thread observes the preceding operation, or
+    // this observes the parked thread.
+    fn wake(&mut self, waiting: *uint, cond: *mut Cond) {
+        unsafe {
+            atomic_fence();
+            if atomic_load_relaxed(&*waiting) != 0 {
+                let _guard = self.mutex.lock_guard();
+                (*cond).signal()
+            }
+        }
+    }
+
+    fn wake_consumer(&mut self) {
+        let waiting: *uint = &self.waiting_consumers;
+        let cond: *mut Cond = &mut self.not_empty;
+        self.wake(waiting, cond)
+    }
+
+    fn wake_producer(&mut self) {
+        let waiting: *uint = &self.waiting_producers;
+        let cond: *mut Cond = &mut self.not_full;
+        self.wake(waiting, cond)
+    }
+
+    fn push(&mut self, item: T, abstime: Option<Time>) -> Option<T> {
+        let mut item = item;
+        loop {
+            item = match self.try_push(item) {
+                None => {
+                    self.wake_consumer();
+                    return None
+                }
+                Some(item) => item
+            };
+            unsafe {
+                let mutex: &mut Mutex = transmute(&mut self.mutex);
+                let mut guard = mutex.lock_guard();
+                atomic_xadd(&mut self.waiting_producers, 1);
+                atomic_fence();
+                let status = if self.is_full() {
+                    match abstime {
+                        Some(t) => self.not_full.wait_until_guard(&mut guard, t),
+                        None => { self.not_full.wait_guard(&mut guard); NoTimeout }
+                    }
+                } else {
+                    NoTimeout
+                };
+                atomic_xsub(&mut self.waiting_producers, 1);
+                if status == Timeout {
+                    return Some(item)
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self, abstime: Option<Time>) -> Option<T> {
+        loop {
+            match self.try_pop() {
+                Some(item) => {
+                    self.wake_producer();
+                    return Some(item)
+                }
+                None => ()
+            }
+            unsafe {
+                let mutex: &mut Mutex = transmute(&mut self.mutex);
+                let mut guard = mutex.lock_guard();
+                atomic_xadd(&mut self.waiting_consumers, 1);
+                atomic_fence();
+                let status = if self.is_empty() {
+                    match abstime {
+                        Some(t) => self.not_empty.wait_until_guard(&mut guard, t),
+                        None => { self.not_empty.wait_guard(&mut guard); NoTimeout }
+                    }
+                } else {
+                    NoTimeout
+                };
+                atomic_xsub(&mut self.waiting_consumers, 1);
+                if status == Timeout {
+                    return None
+                }
+            }
+        }
+    }
+}
+
+/// A bounded, lock-free concurrent queue supporting multiple producers and consumers
+///
+/// The queue is a ring buffer where every slot carries a sequence number, so producers and
+/// consumers only contend on a compare-and-swap of their own end of the queue. The non-blocking
+/// `try_push` and `try_pop` never take a lock.
+///
+/// The blocking operations fall back to a mutex and condition variables when the queue is full or
+/// empty. The other end only takes the mutex to wake them if a thread is actually parked.
+pub struct RingQueue<T> {
+    priv ptr: Arc<RingQueueBox<T>>
+}
+
+impl<T: Send> RingQueue<T> {
+    /// Return a new `RingQueue` instance, holding at most `maximum` elements rounded up to a power
+    /// of two.
+    pub fn new(maximum: uint) -> RingQueue<T> {
+        let mut capacity = 2;
+        while capacity < maximum {
+            capacity <<= 1;
+        }
+        let mut buffer = Vec::with_capacity(capacity);
+        let mut i = 0;
+        while i < capacity {
+            buffer.push(Slot { sequence: i, value: None });
+            i += 1;
+        }
+        let b = RingQueueBox {
+            buffer: buffer,
+            mask: capacity - 1,
+            pad0: [0, ..CACHE_LINE],
+            enqueue_pos: 0,
+            pad1: [0, ..CACHE_LINE],
+            dequeue_pos: 0,
+            pad2: [0, ..CACHE_LINE],
             mutex: Mutex::new(),
+            not_empty: Cond::new(),
+            not_full: Cond::new(),
+            waiting_consumers: 0,
+            waiting_producers: 0,
             no_freeze: NoFreeze
+        };
+        unsafe {
+            RingQueue { ptr: Arc::new_unchecked(b) }
+        }
+    }
+
+    /// Return the maximum number of elements held by the queue.
+    pub fn capacity(&self) -> uint {
+        self.ptr.borrow().mask + 1
+    }
+
+    /// Pop a value from the front of the queue, blocking until the queue is not empty.
+    pub fn pop(&self) -> T {
+        unsafe {
+            let ptr: &mut RingQueueBox<T> = transmute(self.ptr.borrow());
+            ptr.pop(None).get()
+        }
+    }
+
+    /// Pop a value from the front of the queue, or return `None` if the queue is empty.
+    pub fn try_pop(&self) -> Option<T> {
+        unsafe {
+            let ptr: &mut RingQueueBox<T> = transmute(self.ptr.borrow());
+            match ptr.try_pop() {
+                Some(item) => {
+                    ptr.wake_producer();
+                    Some(item)
+                }
+                None => None
+            }
+        }
+    }
+
+    /// Pop a value from the front of the queue, blocking until the queue is not empty or the
+    /// timeout expires.
+    pub fn pop_timeout(&self, reltime: Time) -> Option<T> {
+        unsafe {
+            let ptr: &mut RingQueueBox<T> = transmute(self.ptr.borrow());
+            ptr.pop(Some(deadline(reltime)))
+        }
+    }
+
+    /// Push a value to the back of the queue, blocking until the queue is not full.
+    pub fn push(&self, item: T) {
+        unsafe {
+            let ptr: &mut RingQueueBox<T> = transmute(self.ptr.borrow());
+            ptr.push(item, None);
+        }
+    }
+
+    /// Push a value to the back of the queue, or return `Some(item)` if the queue is full.
+    pub fn try_push(&self, item: T) -> Option<T> {
+        unsafe {
+            let ptr: &mut RingQueueBox<T> = transmute(self.ptr.borrow());
+            match ptr.try_push(item) {
+                None => {
+                    ptr.wake_consumer();
+                    None
+                }
+                full => full
+            }
+        }
+    }
+
+    /// Push a value to the back of the queue, blocking until the queue is not full or the timeout
+    /// expires. If the timeout expires, return `Some(item)`.
+    pub fn push_timeout(&self, item: T, reltime: Time) -> Option<T> {
+        unsafe {
+            let ptr: &mut RingQueueBox<T> = transmute(self.ptr.borrow());
+            ptr.push(item, Some(deadline(reltime)))
+        }
+    }
+}
+
+impl<T> Clone for RingQueue<T> {
+    /// Return a shallow copy of the queue
+    fn clone(&self) -> RingQueue<T> {
+        RingQueue { ptr: self.ptr.clone() }
+    }
+}
+
+// A circular array of a work-stealing deque, indexed by the unbounded positions of the deque. The
+// values are owned by the deque rather than the buffer.
+struct Buffer<T> {
+    storage: *mut T,
+    mask: uint
+}
+
+impl<T: Send> Buffer<T> {
+    fn new(capacity: uint) -> ~Buffer<T> {
+        unsafe {
+            ~Buffer { storage: alloc(capacity * size_of::<T>()) as *mut T, mask: capacity - 1 }
+        }
+    }
+
+    unsafe fn slot(&self, i: int) -> *mut T {
+        offset(self.storage as *T, (i as uint & self.mask) as int) as *mut T
+    }
+
+    unsafe fn get(&self, i: int) -> T {
+        read_ptr(self.slot(i) as *T)
+    }
+
+    unsafe fn put(&self, i: int, item: T) {
+        move_val_init(&mut *self.slot(i), item)
+    }
+
+    // Return a buffer of twice the capacity, holding a copy of the values from `top` to `bottom`
+    unsafe fn grow(&self, bottom: int, top: int) -> ~Buffer<T> {
+        let buffer = Buffer::new((self.mask + 1) * 2);
+        let mut i = top;
+        while i < bottom {
+            copy_nonoverlapping_memory(buffer.slot(i), self.slot(i) as *T, 1);
+            i += 1;
+        }
+        buffer
+    }
+}
+
+#[unsafe_destructor]
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            free(self.storage as *mut u8)
+        }
+    }
+}
+
+static WORK_DEQUE_CAPACITY: uint = 32;
+
+struct WorkDequeBox<T> {
+    top: int,
+    pad: [u8, ..CACHE_LINE],
+    bottom: int,
+    buffer: *mut Buffer<T>,
+    // Buffers replaced by growing, kept alive since a stealer may still be reading from them
+    retired: Vec<~Buffer<T>>,
+    no_freeze: NoFreeze
+}
+
+impl<T: Send> WorkDequeBox<T> {
+    fn push(&mut self, item: T) {
+        unsafe {
+            let bottom = atomic_load_relaxed(&self.bottom);
+            let top = atomic_load_acq(&self.top);
+            let mut buffer = self.buffer;
+            if bottom - top > (*buffer).mask as int {
+                let grown = (*buffer).grow(bottom, top);
+                self.retired.push(transmute(buffer));
+                buffer = transmute(grown);
+                atomic_store_rel(&mut self.buffer, buffer);
+            }
+            (*buffer).put(bottom, item);
+            atomic_fence_rel();
+            atomic_store_relaxed(&mut self.bottom, bottom + 1);
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        unsafe {
+            let bottom = atomic_load_relaxed(&self.bottom) - 1;
+            let buffer = self.buffer;
+            atomic_store_relaxed(&mut self.bottom, bottom);
+            atomic_fence();
+            let top = atomic_load_relaxed(&self.top);
+            if top > bottom {
+                atomic_store_relaxed(&mut self.bottom, bottom + 1);
+                return None
+            }
+            let item = (*buffer).get(bottom);
+            if top == bottom {
+                // The last value, which a stealer may be taking at the same time
+                let won = atomic_cxchg(&mut self.top, top, top + 1) == top;
+                atomic_store_relaxed(&mut self.bottom, bottom + 1);
+                if !won {
+                    forget(item);
+                    return None
+                }
+            }
+            Some(item)
+        }
+    }
+
+    fn steal(&mut self) -> Option<T> {
+        unsafe {
+            let top = atomic_load_acq(&self.top);
+            atomic_fence();
+            let bottom = atomic_load_acq(&self.bottom);
+            if top >= bottom {
+                return None
+            }
+            let buffer = atomic_load_acq(&self.buffer);
+            let item = (*buffer).get(top);
+            if atomic_cxchg(&mut self.top, top, top + 1) != top {
+                // The value was taken by the owner or another stealer, and may have been
+                // overwritten while it was read
+                forget(item);
+                return None
+            }
+            Some(item)
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        unsafe {
+            let top = atomic_load_acq(&self.top);
+            let bottom = atomic_load_acq(&self.bottom);
+            top >= bottom
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for WorkDequeBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let buffer: ~Buffer<T> = transmute(self.buffer);
+            let mut i = self.top;
+            while i < self.bottom {
+                let _item = buffer.get(i);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Return the two halves of a new work-stealing deque.
+///
+/// The deque is the lock-free design by Chase and Lev. The `Worker` pushes and pops values at the
+/// bottom of the deque in LIFO order, while any number of `Stealer` handles take values from the
+/// top in FIFO order.
+pub fn work_deque<T: Send>() -> (Worker<T>, Stealer<T>) {
+    let buffer: *mut Buffer<T> = unsafe { transmute(Buffer::<T>::new(WORK_DEQUE_CAPACITY)) };
+    let b = WorkDequeBox { top: 0, pad: [0, ..CACHE_LINE], bottom: 0, buffer: buffer,
+                           retired: Vec::new(), no_freeze: NoFreeze };
+    unsafe {
+        let ptr = Arc::new_unchecked(b);
+        (Worker { ptr: ptr.clone() }, Stealer { ptr: ptr })
+    }
+}
+
+/// The owner side of a work-stealing deque
+pub struct Worker<T> {
+    priv ptr: Arc<WorkDequeBox<T>>
+}
+
+impl<T: Send> Worker<T> {
+    /// Push a value to the bottom of the deque.
+    pub fn push(&mut self, item: T) {
+        unsafe {
+            let ptr: &mut WorkDequeBox<T> = transmute(self.ptr.borrow());
+            ptr.push(item)
+        }
+    }
+
+    /// Pop the most recently pushed value from the bottom of the deque, or return `None` if the
+    /// deque is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        unsafe {
+            let ptr: &mut WorkDequeBox<T> = transmute(self.ptr.borrow());
+            ptr.pop()
+        }
+    }
+
+    /// Return `true` if the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        self.ptr.borrow().is_empty()
+    }
+}
+
+/// A handle taking values from the top of a work-stealing deque
+pub struct Stealer<T> {
+    priv ptr: Arc<WorkDequeBox<T>>
+}
+
+impl<T: Send> Stealer<T> {
+    /// Take the least recently pushed value from the top of the deque. Return `None` if the deque
+    /// is empty, or if the value was taken by another thread first.
+    pub fn steal(&self) -> Option<T> {
+        unsafe {
+            let ptr: &mut WorkDequeBox<T> = transmute(self.ptr.borrow());
+            ptr.steal()
+        }
+    }
+
+    /// Return `true` if the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        self.ptr.borrow().is_empty()
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    /// Return another handle to the same deque
+    fn clone(&self) -> Stealer<T> {
+        Stealer { ptr: self.ptr.clone() }
+    }
+}
+
+// A lock protecting a `LockedHashMap`, taken in shared mode for lookups
+trait MapLock {
+    fn new() -> Self;
+    unsafe fn lock_shared(&mut self);
+    unsafe fn lock_exclusive(&mut self);
+    unsafe fn unlock(&mut self);
+}
+
+impl MapLock for Mutex {
+    fn new() -> Mutex { Mutex::new() }
+    unsafe fn lock_shared(&mut self) { self.lock() }
+    unsafe fn lock_exclusive(&mut self) { self.lock() }
+    unsafe fn unlock(&mut self) { self.unlock() }
+}
+
+impl MapLock for RwLock {
+    fn new() -> RwLock { RwLock::new() }
+    unsafe fn lock_shared(&mut self) { self.read() }
+    unsafe fn lock_exclusive(&mut self) { self.write() }
+    unsafe fn unlock(&mut self) { self.unlock() }
+}
+
+struct MapGuard<'a, L> {
+    lock: &'a mut L
+}
+
+unsafe fn shared<'a, L: MapLock>(lock: &'a mut L) -> MapGuard<'a, L> {
+    lock.lock_shared();
+    MapGuard { lock: lock }
+}
+
+unsafe fn exclusive<'a, L: MapLock>(lock: &'a mut L) -> MapGuard<'a, L> {
+    lock.lock_exclusive();
+    MapGuard { lock: lock }
+}
+
+#[unsafe_destructor]
+impl<'a, L: MapLock> Drop for MapGuard<'a, L> {
+    fn drop(&mut self) {
+        unsafe {
+            self.lock.unlock()
+        }
+    }
+}
+
+struct LockedHashMap<K, V, H, L = Mutex> {
+    map: HashMap<K, V, H>,
+    lock: L,
+    no_freeze: NoFreeze
+}
+
+impl<K: Hash + Eq, V, H: Hasher, L: MapLock> LockedHashMap<K, V, H, L> {
+    fn with_capacity_and_hasher(hasher: H, capacity: uint) -> LockedHashMap<K, V, H, L> {
+        LockedHashMap {
+            map: HashMap::with_capacity_and_hasher(hasher, capacity),
+            lock: MapLock::new(),
+            no_freeze: NoFreeze
+        }
+    }
+
+    fn swap(&mut self, k: K, v: V) -> Option<V> {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            self.map.swap(k, v)
+        }
+    }
+
+    fn pop(&mut self, k: &K) -> Option<V> {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            self.map.pop(k)
+        }
+    }
+
+    fn with<R>(&mut self, k: &K, f: |&V| -> R) -> Option<R> {
+        unsafe {
+            let _guard = shared(&mut self.lock);
+            match self.map.find(k) {
+                Some(v) => Some(f(v)),
+                None => None
+            }
+        }
+    }
+
+    fn with_mut<R>(&mut self, k: &K, f: |&mut V| -> R) -> Option<R> {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            match self.map.find_mut(k) {
+                Some(v) => Some(f(v)),
+                None => None
+            }
+        }
+    }
+
+    fn compute(&mut self, k: K, f: |Option<&V>| -> Option<V>) -> Option<V> {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            match self.map.entry(k) {
+                Occupied(mut entry) => {
+                    match f(Some(entry.get())) {
+                        Some(v) => Some(entry.set(v)),
+                        None => Some(entry.take())
+                    }
+                }
+                Vacant(entry) => {
+                    match f(None) {
+                        Some(v) => { entry.set(v); }
+                        None => ()
+                    }
+                    None
+                }
+            }
+        }
+    }
+
+    fn remove_if(&mut self, k: &K, f: |&V| -> bool) -> Option<V> {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            let remove = match self.map.find(k) {
+                Some(v) => f(v),
+                None => false
+            };
+            if remove { self.map.pop(k) } else { None }
         }
     }
 
-    fn swap(&mut self, k: K, v: V) -> Option<V> {
+    fn len(&mut self) -> uint {
         unsafe {
-            let _guard = self.mutex.lock_guard();
-            self.map.swap(k, v)
+            let _guard = shared(&mut self.lock);
+            self.map.len()
         }
     }
 
-    fn pop(&mut self, k: &K) -> Option<V> {
+    fn clear(&mut self) {
         unsafe {
-            let _guard = self.mutex.lock_guard();
-            self.map.pop(k)
+            let _guard = exclusive(&mut self.lock);
+            self.map.clear()
+        }
+    }
+
+    fn retain(&mut self, f: |&K, &mut V| -> bool) {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            self.map.retain(f)
+        }
+    }
+
+    fn for_each(&mut self, f: |&K, &V|) {
+        unsafe {
+            let _guard = shared(&mut self.lock);
+            for (k, v) in self.map.iter() {
+                f(k, v)
+            }
         }
     }
 }
 
-impl<K: Hash + Eq, V: Clone> LockedHashMap<K, V> {
+impl<K: Hash + Eq, V: Clone, H: Hasher, L: MapLock> LockedHashMap<K, V, H, L> {
     fn find(&mut self, k: &K) -> Option<V> {
         unsafe {
-            let _guard = self.mutex.lock_guard();
+            let _guard = shared(&mut self.lock);
             self.map.find(k).map(|v| v.clone())
         }
     }
+
+    fn find_or_insert_with(&mut self, k: K, f: |&K| -> V) -> V {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            self.map.find_or_insert_with(k, f).clone()
+        }
+    }
+
+    fn insert_or_update_with(&mut self, k: K, v: V, f: |&K, &mut V|) -> V {
+        unsafe {
+            let _guard = exclusive(&mut self.lock);
+            self.map.insert_or_update_with(k, v, f).clone()
+        }
+    }
 }
 
 /// A concurrent hash table based a single lock per instance
-pub struct ConcurrentHashMap<K, V> {
-    priv ptr: Arc<LockedHashMap<K, V>>
+pub struct ConcurrentHashMap<K, V, H = State> {
+    priv ptr: Arc<LockedHashMap<K, V, H>>
 }
 
 impl<K: Hash + Eq + Send, V: Send> ConcurrentHashMap<K, V> {
+    /// Create a new `ConcurrentHashMap` with a random hash key.
+    pub fn new() -> ConcurrentHashMap<K, V> {
+        ConcurrentHashMap::with_capacity(0)
+    }
+
+    /// Create a new `ConcurrentHashMap` with a random hash key and initial `capacity`.
+    pub fn with_capacity(capacity: uint) -> ConcurrentHashMap<K, V> {
+        let (k0, k1) = random_keys();
+        ConcurrentHashMap::with_capacity_and_keys(k0, k1, capacity)
+    }
+
     /// Create a new `ConcurrentHashMap` with the specified 128-bit hash key (`k0` and `k1`) and
     /// initial `capacity`.
     pub fn with_capacity_and_keys(k0: u64, k1: u64, capacity: uint) -> ConcurrentHashMap<K, V> {
-        let b = LockedHashMap::with_capacity_and_keys(k0, k1, capacity);
+        ConcurrentHashMap::with_capacity_and_hasher(State::new(k0, k1), capacity)
+    }
+}
+
+impl<K: Hash + Eq + Send, V: Send, H: Hasher + Send> ConcurrentHashMap<K, V, H> {
+    /// Create a new `ConcurrentHashMap` using `hasher` as the hash function, with an initial
+    /// `capacity`.
+    pub fn with_capacity_and_hasher(hasher: H, capacity: uint) -> ConcurrentHashMap<K, V, H> {
+        let b = LockedHashMap::with_capacity_and_hasher(hasher, capacity);
         unsafe {
             ConcurrentHashMap { ptr: Arc::new_unchecked(b) }
         }
@@ -475,7 +2313,7 @@ impl<K: Hash + Eq + Send, V: Send> ConcurrentHashMap<K, V> {
     /// Insert a key-value pair into the hash table. Return the old value corresponding to the key.
     pub fn swap(&self, k: K, v: V) -> Option<V> {
         unsafe {
-            let ptr: &mut LockedHashMap<K, V> = transmute(self.ptr.borrow());
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
             ptr.swap(k, v)
         }
     }
@@ -483,61 +2321,268 @@ impl<K: Hash + Eq + Send, V: Send> ConcurrentHashMap<K, V> {
     /// Remove a key-value pair from the map. Return the value corresponding to the key.
     pub fn pop(&self, k: &K) -> Option<V> {
         unsafe {
-            let ptr: &mut LockedHashMap<K, V> = transmute(self.ptr.borrow());
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
             ptr.pop(k)
         }
     }
+
+    /// Call `f` with a reference to the value corresponding to the key, while holding the lock.
+    /// Return the result of `f`, or `None` if the key is not present.
+    pub fn with<R>(&self, k: &K, f: |&V| -> R) -> Option<R> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
+            ptr.with(k, f)
+        }
+    }
+
+    /// Call `f` with a mutable reference to the value corresponding to the key, while holding the
+    /// lock. Return the result of `f`, or `None` if the key is not present.
+    pub fn with_mut<R>(&self, k: &K, f: |&mut V| -> R) -> Option<R> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
+            ptr.with_mut(k, f)
+        }
+    }
+
+    /// Call `f` with the value corresponding to the key, or `None` if the key is not present. If
+    /// `f` returns a value, it replaces the current one or is inserted, otherwise the key is
+    /// removed. The lookup and update are atomic. Return the previous value.
+    pub fn compute(&self, k: K, f: |Option<&V>| -> Option<V>) -> Option<V> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
+            ptr.compute(k, f)
+        }
+    }
+
+    /// Remove a key-value pair from the map if `f` returns `true` for the value. The check and
+    /// removal are atomic. Return the removed value.
+    pub fn remove_if(&self, k: &K, f: |&V| -> bool) -> Option<V> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
+            ptr.remove_if(k, f)
+        }
+    }
 }
 
-impl<K: Hash + Eq, V: Clone> ConcurrentHashMap<K, V> {
+impl<K: Hash + Eq, V: Clone, H: Hasher> ConcurrentHashMap<K, V, H> {
     /// Return the value corresponding to the key via `clone`.
     ///
     /// A reference cannot be returned directly, because a lock has to be obtained and released by
     /// the function.
     pub fn find(&self, k: &K) -> Option<V> {
         unsafe {
-            let ptr: &mut LockedHashMap<K, V> = transmute(self.ptr.borrow());
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
             ptr.find(k)
         }
     }
+
+    /// Return the value corresponding to the key via `clone`, or create, insert and return a new
+    /// value if it doesn't exist. The lookup and insertion are atomic.
+    pub fn find_or_insert_with(&self, k: K, f: |&K| -> V) -> V {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
+            ptr.find_or_insert_with(k, f)
+        }
+    }
+
+    /// Insert a key-value pair into the map if the key is not already present. Otherwise, modify
+    /// the existing value for the key. The lookup and update are atomic. Return the new or
+    /// modified value via `clone`.
+    pub fn insert_or_update_with(&self, k: K, v: V, f: |&K, &mut V|) -> V {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H> = transmute(self.ptr.borrow());
+            ptr.insert_or_update_with(k, v, f)
+        }
+    }
 }
 
-impl<K, V> Clone for ConcurrentHashMap<K, V> {
+impl<K, V, H> Clone for ConcurrentHashMap<K, V, H> {
     /// Return a shallow copy of the map
-    fn clone(&self) -> ConcurrentHashMap<K, V> {
+    fn clone(&self) -> ConcurrentHashMap<K, V, H> {
         ConcurrentHashMap { ptr: self.ptr.clone() }
     }
 }
 
-struct ShardMapBox<K, V> {
-    maps: Vec<LockedHashMap<K, V>>,
-    k0: u64,
-    k1: u64,
+/// A concurrent hash table based on a single reader-writer lock per instance
+///
+/// Lookups with `find` and `with` share the lock, so they can run in parallel. Insertions, removals
+/// and `with_mut` take the lock exclusively.
+pub struct RwLockHashMap<K, V, H = State> {
+    priv ptr: Arc<LockedHashMap<K, V, H, RwLock>>
+}
+
+impl<K: Hash + Eq + Send, V: Send> RwLockHashMap<K, V> {
+    /// Create a new `RwLockHashMap` with a random hash key.
+    pub fn new() -> RwLockHashMap<K, V> {
+        RwLockHashMap::with_capacity(0)
+    }
+
+    /// Create a new `RwLockHashMap` with a random hash key and initial `capacity`.
+    pub fn with_capacity(capacity: uint) -> RwLockHashMap<K, V> {
+        let (k0, k1) = random_keys();
+        RwLockHashMap::with_capacity_and_keys(k0, k1, capacity)
+    }
+
+    /// Create a new `RwLockHashMap` with the specified 128-bit hash key (`k0` and `k1`) and
+    /// initial `capacity`.
+    pub fn with_capacity_and_keys(k0: u64, k1: u64, capacity: uint) -> RwLockHashMap<K, V> {
+        RwLockHashMap::with_capacity_and_hasher(State::new(k0, k1), capacity)
+    }
+}
+
+impl<K: Hash + Eq + Send, V: Send, H: Hasher + Send> RwLockHashMap<K, V, H> {
+    /// Create a new `RwLockHashMap` using `hasher` as the hash function, with an initial
+    /// `capacity`.
+    pub fn with_capacity_and_hasher(hasher: H, capacity: uint) -> RwLockHashMap<K, V, H> {
+        let b = LockedHashMap::with_capacity_and_hasher(hasher, capacity);
+        unsafe {
+            RwLockHashMap { ptr: Arc::new_unchecked(b) }
+        }
+    }
+
+    /// Insert a key-value pair into the hash table. Return the old value corresponding to the key.
+    pub fn swap(&self, k: K, v: V) -> Option<V> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.swap(k, v)
+        }
+    }
+
+    /// Remove a key-value pair from the map. Return the value corresponding to the key.
+    pub fn pop(&self, k: &K) -> Option<V> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.pop(k)
+        }
+    }
+
+    /// Call `f` with a reference to the value corresponding to the key, while holding the lock in
+    /// shared mode. Return the result of `f`, or `None` if the key is not present.
+    pub fn with<R>(&self, k: &K, f: |&V| -> R) -> Option<R> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.with(k, f)
+        }
+    }
+
+    /// Call `f` with a mutable reference to the value corresponding to the key, while holding the
+    /// lock in exclusive mode. Return the result of `f`, or `None` if the key is not present.
+    pub fn with_mut<R>(&self, k: &K, f: |&mut V| -> R) -> Option<R> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.with_mut(k, f)
+        }
+    }
+
+    /// Call `f` with the value corresponding to the key, or `None` if the key is not present. If
+    /// `f` returns a value, it replaces the current one or is inserted, otherwise the key is
+    /// removed. The lookup and update are atomic. Return the previous value.
+    pub fn compute(&self, k: K, f: |Option<&V>| -> Option<V>) -> Option<V> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.compute(k, f)
+        }
+    }
+
+    /// Remove a key-value pair from the map if `f` returns `true` for the value. The check and
+    /// removal are atomic. Return the removed value.
+    pub fn remove_if(&self, k: &K, f: |&V| -> bool) -> Option<V> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.remove_if(k, f)
+        }
+    }
+}
+
+impl<K: Hash + Eq, V: Clone, H: Hasher> RwLockHashMap<K, V, H> {
+    /// Return the value corresponding to the key via `clone`.
+    ///
+    /// A reference cannot be returned directly, because a lock has to be obtained and released by
+    /// the function.
+    pub fn find(&self, k: &K) -> Option<V> {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.find(k)
+        }
+    }
+
+    /// Return the value corresponding to the key via `clone`, or create, insert and return a new
+    /// value if it doesn't exist. The lookup and insertion are atomic.
+    pub fn find_or_insert_with(&self, k: K, f: |&K| -> V) -> V {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.find_or_insert_with(k, f)
+        }
+    }
+
+    /// Insert a key-value pair into the map if the key is not already present. Otherwise, modify
+    /// the existing value for the key. The lookup and update are atomic. Return the new or
+    /// modified value via `clone`.
+    pub fn insert_or_update_with(&self, k: K, v: V, f: |&K, &mut V|) -> V {
+        unsafe {
+            let ptr: &mut LockedHashMap<K, V, H, RwLock> = transmute(self.ptr.borrow());
+            ptr.insert_or_update_with(k, v, f)
+        }
+    }
+}
+
+impl<K, V, H> Clone for RwLockHashMap<K, V, H> {
+    /// Return a shallow copy of the map
+    fn clone(&self) -> RwLockHashMap<K, V, H> {
+        RwLockHashMap { ptr: self.ptr.clone() }
+    }
+}
+
+struct ShardMapBox<K, V, H> {
+    maps: Vec<LockedHashMap<K, V, H>>,
+    hasher: H,
     no_freeze: NoFreeze
 }
 
-impl<K: Hash + Eq, V> ShardMapBox<K, V> {
+impl<K: Hash + Eq, V, H: Hasher> ShardMapBox<K, V, H> {
     fn get_shard(&self, k: &K) -> uint {
-        k.hash(self.k0, self.k1) as uint % self.maps.len()
+        // The shards hash with the same function, so the shard is picked with the upper bits to
+        // leave the lower bits distributed within each shard.
+        (k.hash_with(&self.hasher) >> 32) as uint % self.maps.len()
     }
 }
 
 /// A concurrent hash table distributing keys across shards, with locking on a per-shard basis
-pub struct ShardMap<K, V> {
-    priv ptr: Arc<ShardMapBox<K, V>>
+pub struct ShardMap<K, V, H = State> {
+    priv ptr: Arc<ShardMapBox<K, V, H>>
 }
 
 impl<K: Hash + Eq + Send, V: Send> ShardMap<K, V> {
+    /// Create a new `ShardMap` with `shards` internal hash tables and a random hash key.
+    pub fn new(shards: uint) -> ShardMap<K, V> {
+        ShardMap::with_capacity(shards, 0)
+    }
+
+    /// Create a new `ShardMap` with `shards` internal hash tables, a random hash key and an
+    /// initial `capacity`.
+    pub fn with_capacity(shards: uint, capacity: uint) -> ShardMap<K, V> {
+        let (k0, k1) = random_keys();
+        ShardMap::with_capacity_and_keys(shards, k0, k1, capacity)
+    }
+
     /// Create a new `ShardMap` with `shards` internal hash tables, the specified 128-bit hash key
     /// (`k0` and `k1`) and an initial `capacity`.
     pub fn with_capacity_and_keys(shards: uint, k0: u64, k1: u64, capacity: uint) -> ShardMap<K, V> {
+        ShardMap::with_capacity_and_hasher(shards, State::new(k0, k1), capacity)
+    }
+}
+
+impl<K: Hash + Eq + Send, V: Send, H: Hasher + Send> ShardMap<K, V, H> {
+    /// Create a new `ShardMap` with `shards` internal hash tables, using `hasher` as the hash
+    /// function, with an initial `capacity`.
+    pub fn with_capacity_and_hasher(shards: uint, hasher: H, capacity: uint) -> ShardMap<K, V, H> {
         let mut xs = Vec::with_capacity(shards);
         let mut i = 0;
         while i < shards {
-            xs.push(LockedHashMap::with_capacity_and_keys(k0, k1, capacity));
+            xs.push(LockedHashMap::with_capacity_and_hasher(hasher.clone(), capacity));
             i += 1;
         }
-        let inner = ShardMapBox { maps: xs, k0: k0, k1: k1, no_freeze: NoFreeze };
+        let inner = ShardMapBox { maps: xs, hasher: hasher, no_freeze: NoFreeze };
         unsafe {
             ShardMap { ptr: Arc::new_unchecked(inner) }
         }
@@ -546,7 +2591,7 @@ impl<K: Hash + Eq + Send, V: Send> ShardMap<K, V> {
     /// Insert a key-value pair into the hash table. Return the old value corresponding to the key.
     pub fn swap(&self, k: K, v: V) -> Option<V> {
         unsafe {
-            let ptr: &mut ShardMapBox<K, V> = transmute(self.ptr.borrow());
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
             let shard = ptr.get_shard(&k);
             ptr.maps.as_mut_slice()[shard].swap(k, v)
         }
@@ -555,30 +2600,332 @@ impl<K: Hash + Eq + Send, V: Send> ShardMap<K, V> {
     /// Remove a key-value pair from the map. Return the value corresponding to the key.
     pub fn pop(&self, k: &K) -> Option<V> {
         unsafe {
-            let ptr: &mut ShardMapBox<K, V> = transmute(self.ptr.borrow());
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
             let shard = ptr.get_shard(k);
             ptr.maps.as_mut_slice()[shard].pop(k)
         }
     }
+
+    /// Call `f` with a reference to the value corresponding to the key, while holding the lock of
+    /// its shard. Return the result of `f`, or `None` if the key is not present.
+    pub fn with<R>(&self, k: &K, f: |&V| -> R) -> Option<R> {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(k);
+            ptr.maps.as_mut_slice()[shard].with(k, f)
+        }
+    }
+
+    /// Call `f` with a mutable reference to the value corresponding to the key, while holding the
+    /// lock of its shard. Return the result of `f`, or `None` if the key is not present.
+    pub fn with_mut<R>(&self, k: &K, f: |&mut V| -> R) -> Option<R> {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(k);
+            ptr.maps.as_mut_slice()[shard].with_mut(k, f)
+        }
+    }
+
+    /// Call `f` with the value corresponding to the key, or `None` if the key is not present. If
+    /// `f` returns a value, it replaces the current one or is inserted, otherwise the key is
+    /// removed. The lookup and update are atomic. Return the previous value.
+    pub fn compute(&self, k: K, f: |Option<&V>| -> Option<V>) -> Option<V> {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(&k);
+            ptr.maps.as_mut_slice()[shard].compute(k, f)
+        }
+    }
+
+    /// Remove a key-value pair from the map if `f` returns `true` for the value. The check and
+    /// removal are atomic. Return the removed value.
+    pub fn remove_if(&self, k: &K, f: |&V| -> bool) -> Option<V> {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(k);
+            ptr.maps.as_mut_slice()[shard].remove_if(k, f)
+        }
+    }
+
+    /// Return the number of entries in the map, summed across the shards.
+    ///
+    /// The shards are locked one at a time, so the result does not reflect a single point in time
+    /// if the map is concurrently modified.
+    pub fn len(&self) -> uint {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            let mut len = 0;
+            for map in mut_iter(ptr.maps.as_mut_slice()) {
+                len += map.len();
+            }
+            len
+        }
+    }
+
+    /// Remove all entries from the map, locking the shards one at a time.
+    pub fn clear(&self) {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            for map in mut_iter(ptr.maps.as_mut_slice()) {
+                map.clear()
+            }
+        }
+    }
+
+    /// Retain only the entries for which `f` returns `true`, locking the shards one at a time.
+    pub fn retain(&self, f: |&K, &mut V| -> bool) {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            for map in mut_iter(ptr.maps.as_mut_slice()) {
+                map.retain(|k, v| f(k, v))
+            }
+        }
+    }
+
+    /// Call `f` with every entry of the map, while holding the lock of its shard.
+    ///
+    /// The shards are locked one at a time. Every entry present for the whole call is visited
+    /// exactly once, while entries inserted or removed concurrently may or may not be.
+    pub fn for_each(&self, f: |&K, &V|) {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            for map in mut_iter(ptr.maps.as_mut_slice()) {
+                map.for_each(|k, v| f(k, v))
+            }
+        }
+    }
 }
 
-impl<K: Hash + Eq, V: Clone> ShardMap<K, V> {
+impl<K: Hash + Eq, V: Clone, H: Hasher> ShardMap<K, V, H> {
     /// Return the value corresponding to the key via `clone`.
     ///
     /// A reference cannot be returned directly, because a lock has to be obtained and released by
     /// the function.
     pub fn find(&self, k: &K) -> Option<V> {
         unsafe {
-            let ptr: &mut ShardMapBox<K, V> = transmute(self.ptr.borrow());
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
             let shard = ptr.get_shard(k);
             ptr.maps.as_mut_slice()[shard].find(k)
         }
     }
+
+    /// Return the value corresponding to the key via `clone`, or create, insert and return a new
+    /// value if it doesn't exist. The lookup and insertion are atomic.
+    pub fn find_or_insert_with(&self, k: K, f: |&K| -> V) -> V {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(&k);
+            ptr.maps.as_mut_slice()[shard].find_or_insert_with(k, f)
+        }
+    }
+
+    /// Insert a key-value pair into the map if the key is not already present. Otherwise, modify
+    /// the existing value for the key. The lookup and update are atomic. Return the new or
+    /// modified value via `clone`.
+    pub fn insert_or_update_with(&self, k: K, v: V, f: |&K, &mut V|) -> V {
+        unsafe {
+            let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(&k);
+            ptr.maps.as_mut_slice()[shard].insert_or_update_with(k, v, f)
+        }
+    }
+
+    /// Return an iterator over copies of the entries of the map.
+    ///
+    /// Each shard is copied under its lock when the iterator reaches it, so the entries of a shard
+    /// are a consistent snapshot of that shard. The shards are copied at different times, so the
+    /// iterator does not reflect a single point in time if the map is concurrently modified.
+    pub fn snapshot(&self) -> Snapshot<K, V, H> {
+        Snapshot { ptr: self.ptr.clone(), shard: 0, entries: Vec::new() }
+    }
 }
 
-impl<K, V> Clone for ShardMap<K, V> {
+impl<K, V, H> Clone for ShardMap<K, V, H> {
     /// Return a shallow copy of the map
-    fn clone(&self) -> ShardMap<K, V> {
+    fn clone(&self) -> ShardMap<K, V, H> {
         ShardMap { ptr: self.ptr.clone() }
     }
 }
+
+/// An iterator over copies of the entries of a `ShardMap`, copying one shard at a time
+pub struct Snapshot<K, V, H> {
+    priv ptr: Arc<ShardMapBox<K, V, H>>,
+    priv shard: uint,
+    priv entries: Vec<(K, V)>
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, H: Hasher> Iterator<(K, V)> for Snapshot<K, V, H> {
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            match self.entries.pop() {
+                Some(entry) => return Some(entry),
+                None => ()
+            }
+            unsafe {
+                let ptr: &mut ShardMapBox<K, V, H> = transmute(self.ptr.borrow());
+                if self.shard == ptr.maps.len() {
+                    return None
+                }
+                let entries = &mut self.entries;
+                ptr.maps.as_mut_slice()[self.shard].for_each(|k, v| {
+                    entries.push((k.clone(), v.clone()))
+                });
+                self.shard += 1;
+            }
+        }
+    }
+}
+
+struct LockedLruCache<K, V, H> {
+    cache: LruCache<K, V, H>,
+    mutex: Mutex,
+    hits: uint,
+    misses: uint,
+    evictions: uint
+}
+
+struct LruShardBox<K, V, H> {
+    shards: Vec<LockedLruCache<K, V, H>>,
+    hasher: H,
+    no_freeze: NoFreeze
+}
+
+impl<K: HashBytes + Eq, V, H: Hasher> LruShardBox<K, V, H> {
+    fn get_shard<'a>(&'a mut self, k: &K) -> &'a mut LockedLruCache<K, V, H> {
+        // The shards hash with the same function, so the shard is picked with the upper bits to
+        // leave the lower bits distributed within each shard.
+        let shard = (k.hash_with(&self.hasher) >> 32) as uint % self.shards.len();
+        &mut self.shards.as_mut_slice()[shard]
+    }
+}
+
+/// Counters of the operations on a `ConcurrentLruCache`
+pub struct CacheStats {
+    /// The number of lookups finding a value
+    hits: uint,
+    /// The number of lookups not finding a value
+    misses: uint,
+    /// The number of values removed to make room for new ones
+    evictions: uint
+}
+
+/// A concurrent LRU cache distributing keys across shards, with locking on a per-shard basis
+///
+/// Every shard is an `LruCache` with its own capacity, so the least-recently-used entry is evicted
+/// from the shard of the inserted key rather than the whole cache.
+pub struct ConcurrentLruCache<K, V, H = State> {
+    priv ptr: Arc<LruShardBox<K, V, H>>
+}
+
+impl<K: HashBytes + Eq + Send, V: Send> ConcurrentLruCache<K, V> {
+    /// Create a cache with `shards` internal caches holding at most `capacity` items each, using
+    /// SipHash-2-4 with a random key.
+    pub fn new(shards: uint, capacity: uint) -> ConcurrentLruCache<K, V> {
+        let (k0, k1) = random_keys();
+        ConcurrentLruCache::with_keys(shards, k0, k1, capacity)
+    }
+
+    /// Create a cache with `shards` internal caches holding at most `capacity` items each, using
+    /// SipHash-2-4 with the specified 128-bit key (`k0` and `k1`).
+    pub fn with_keys(shards: uint, k0: u64, k1: u64, capacity: uint) -> ConcurrentLruCache<K, V> {
+        ConcurrentLruCache::with_hasher(shards, State::new(k0, k1), capacity)
+    }
+}
+
+impl<K: HashBytes + Eq + Send, V: Send, H: Hasher + Send> ConcurrentLruCache<K, V, H> {
+    /// Create a cache with `shards` internal caches holding at most `capacity` items each, using
+    /// `hasher` as the hash function.
+    pub fn with_hasher(shards: uint, hasher: H, capacity: uint) -> ConcurrentLruCache<K, V, H> {
+        let mut xs = Vec::with_capacity(shards);
+        let mut i = 0;
+        while i < shards {
+            xs.push(LockedLruCache { cache: LruCache::with_hasher(hasher.clone(), capacity),
+                                     mutex: Mutex::new(), hits: 0, misses: 0, evictions: 0 });
+            i += 1;
+        }
+        let inner = LruShardBox { shards: xs, hasher: hasher, no_freeze: NoFreeze };
+        unsafe {
+            ConcurrentLruCache { ptr: Arc::new_unchecked(inner) }
+        }
+    }
+
+    /// Put a key-value pair into the cache, evicting the least-recently-used pair of its shard if
+    /// the shard is full.
+    pub fn put(&self, k: K, v: V) {
+        unsafe {
+            let ptr: &mut LruShardBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(&k);
+            let _guard = shard.mutex.lock_guard();
+            if shard.cache.put(k, v).is_some() {
+                shard.evictions += 1;
+            }
+        }
+    }
+
+    /// Remove and return a value corresponding to the key from the cache.
+    pub fn pop(&self, k: &K) -> Option<V> {
+        unsafe {
+            let ptr: &mut LruShardBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(k);
+            let _guard = shard.mutex.lock_guard();
+            shard.cache.pop(k)
+        }
+    }
+
+    /// Return the number of key-value pairs in the cache, summed across the shards.
+    pub fn len(&self) -> uint {
+        unsafe {
+            let ptr: &mut LruShardBox<K, V, H> = transmute(self.ptr.borrow());
+            let mut len = 0;
+            for shard in mut_iter(ptr.shards.as_mut_slice()) {
+                let _guard = shard.mutex.lock_guard();
+                len += shard.cache.len();
+            }
+            len
+        }
+    }
+
+    /// Return the counters of the cache, summed across the shards.
+    pub fn stats(&self) -> CacheStats {
+        unsafe {
+            let ptr: &mut LruShardBox<K, V, H> = transmute(self.ptr.borrow());
+            let mut stats = CacheStats { hits: 0, misses: 0, evictions: 0 };
+            for shard in mut_iter(ptr.shards.as_mut_slice()) {
+                let _guard = shard.mutex.lock_guard();
+                stats.hits += shard.hits;
+                stats.misses += shard.misses;
+                stats.evictions += shard.evictions;
+            }
+            stats
+        }
+    }
+}
+
+impl<K: HashBytes + Eq, V: Clone, H: Hasher> ConcurrentLruCache<K, V, H> {
+    /// Return a value corresponding to the key in the cache via `clone`, marking it as the most
+    /// recently used.
+    pub fn get(&self, k: &K) -> Option<V> {
+        unsafe {
+            let ptr: &mut LruShardBox<K, V, H> = transmute(self.ptr.borrow());
+            let shard = ptr.get_shard(k);
+            let _guard = shard.mutex.lock_guard();
+            match shard.cache.get(k) {
+                Some(v) => {
+                    shard.hits += 1;
+                    Some(v.clone())
+                }
+                None => {
+                    shard.misses += 1;
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, H> Clone for ConcurrentLruCache<K, V, H> {
+    /// Return a shallow copy of the cache
+    fn clone(&self) -> ConcurrentLruCache<K, V, H> {
+        ConcurrentLruCache { ptr: self.ptr.clone() }
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+//! Random bytes for seeding keyed hash functions
+//!
+//! With `--cfg libc`, the bytes come from the operating system via the `getrandom` system call,
+//! falling back to reading `/dev/urandom` on kernels or architectures without it.
+//!
+//! Freestanding builds have no operating system to ask, so calling `fill` requires linking in a
+//! definition of the entropy hook:
+//!
+//! ```rust
+//! #[no_mangle]
+//! pub extern "C" fn core_fill_entropy(buf: *mut u8, len: uint) {
+//!     // fill `len` bytes at `buf` from a hardware RNG, a seed in flash, ...
+//! }
+//! ```
+//!
+//! The hook is only referenced when `fill` is called, so programs which never ask for random bytes
+//! do not have to provide it.
+
+use container::Container;
+use slice::to_mut_ptr;
+#[cfg(libc)]
+use c_types::{c_int, c_long};
+#[cfg(libc)]
+use fail::{EINTR, abort};
+#[cfg(libc)]
+use os::errno;
+#[cfg(libc)]
+use slice::{mut_slice_from, to_ptr};
+
+#[cfg(not(libc))]
+extern {
+    fn core_fill_entropy(buf: *mut u8, len: uint);
+}
+
+/// Fill `buf` with random bytes suitable for use as hash keys.
+#[cfg(not(libc))]
+pub fn fill(buf: &mut [u8]) {
+    unsafe { core_fill_entropy(to_mut_ptr(buf), buf.len()) }
+}
+
+#[cfg(libc)]
+extern {
+    fn syscall(number: c_long, ...) -> c_long;
+    fn open(path: *u8, flags: c_int, ...) -> c_int;
+    fn read(fd: c_int, buf: *mut u8, count: uint) -> int;
+    fn close(fd: c_int) -> c_int;
+}
+
+#[cfg(libc, target_os = "linux", target_arch = "x86_64")]
+static SYS_GETRANDOM: c_long = 318;
+#[cfg(libc, target_os = "linux", target_arch = "x86")]
+static SYS_GETRANDOM: c_long = 355;
+#[cfg(libc, target_os = "linux", target_arch = "arm")]
+static SYS_GETRANDOM: c_long = 384;
+#[cfg(libc, target_os = "linux", target_arch = "aarch64")]
+static SYS_GETRANDOM: c_long = 278;
+#[cfg(libc, target_os = "linux", target_arch = "mips")]
+static SYS_GETRANDOM: c_long = 4353;
+#[cfg(libc, target_os = "linux", target_arch = "powerpc")]
+static SYS_GETRANDOM: c_long = 359;
+
+#[cfg(libc)]
+static ENOSYS: c_int = 38;
+#[cfg(libc)]
+static O_RDONLY: c_int = 0;
+
+/// Fill `buf` with random bytes, returning false if the system call is unavailable.
+#[cfg(libc, target_os = "linux", target_arch = "x86_64")]
+#[cfg(libc, target_os = "linux", target_arch = "x86")]
+#[cfg(libc, target_os = "linux", target_arch = "arm")]
+#[cfg(libc, target_os = "linux", target_arch = "aarch64")]
+#[cfg(libc, target_os = "linux", target_arch = "mips")]
+#[cfg(libc, target_os = "linux", target_arch = "powerpc")]
+fn getrandom(buf: &mut [u8]) -> bool {
+    let mut done = 0;
+    while done < buf.len() {
+        let rest = mut_slice_from(buf, done);
+        let ret = unsafe { syscall(SYS_GETRANDOM, to_mut_ptr(rest), rest.len(), 0 as c_int) };
+        if ret == -1 {
+            let code = errno();
+            if code == ENOSYS {
+                return false
+            } else if code != EINTR {
+                abort()
+            }
+        } else {
+            done += ret as uint;
+        }
+    }
+    true
+}
+
+// Without a known system call number, always read from `/dev/urandom`
+#[cfg(libc, not(target_os = "linux"))]
+#[cfg(libc, not(target_arch = "x86_64"), not(target_arch = "x86"), not(target_arch = "arm"),
+      not(target_arch = "aarch64"), not(target_arch = "mips"), not(target_arch = "powerpc"))]
+fn getrandom(_: &mut [u8]) -> bool {
+    false
+}
+
+#[cfg(libc)]
+fn urandom(buf: &mut [u8]) {
+    unsafe {
+        let fd = open(to_ptr(bytes!("/dev/urandom", 0)), O_RDONLY);
+        if fd == -1 {
+            abort()
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let rest = mut_slice_from(buf, done);
+            let ret = read(fd, to_mut_ptr(rest), rest.len());
+            if ret == -1 {
+                if errno() != EINTR {
+                    abort()
+                }
+            } else if ret == 0 {
+                abort()
+            } else {
+                done += ret as uint;
+            }
+        }
+        close(fd);
+    }
+}
+
+/// Fill `buf` with random bytes suitable for use as hash keys.
+#[cfg(libc)]
+pub fn fill(buf: &mut [u8]) {
+    if !getrandom(buf) {
+        urandom(buf)
+    }
+}
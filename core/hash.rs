@@ -10,27 +10,57 @@
 
 use container::Container;
 use iter::Iterator;
-use slice::iter;
+use slice::{Items, MutItems, iter, mut_iter};
 use option::{None, Option, Some};
 use fail::abort;
-use cmp::{Eq, max};
-use vec::Vec;
-use mem::{replace, size_of};
+use cmp::{Eq, Equiv, max};
+use clone::Clone;
+use ops::Drop;
+use vec::{Vec, MoveItems};
+use mem::{replace, swap, size_of, transmute};
+use str::as_bytes;
+use rc::Rc;
+use arc::Arc;
+use atomic::{atomic_cxchg, atomic_load_acq, atomic_store_rel, atomic_xadd_relaxed};
+use entropy::fill;
+use thread::deschedule;
 
 pub trait Hash {
+    /// Hash the value with SipHash-2-4, keyed by `k0` and `k1`.
     fn hash(&self, k0: u64, k1: u64) -> u64;
+
+    /// Hash the value by feeding it to a copy of `hasher`.
+    fn hash_with<H: Hasher>(&self, hasher: &H) -> u64;
 }
 
 pub trait HashBytes {
     fn hash_bytes(&self, f: |&[u8]|);
 }
 
+/// A streaming hash function.
+///
+/// A `Hasher` value holds the initial state of the function, including any key. It is cloned for
+/// each value hashed, so hash tables can be parameterized over the function by storing a single
+/// instance.
+pub trait Hasher: Clone {
+    /// Feed bytes into the hash function.
+    fn write(&mut self, msg: &[u8]);
+
+    /// Return the hash of the bytes written so far.
+    fn finish(&self) -> u64;
+}
+
 impl<A: HashBytes> Hash for A {
     #[inline]
     fn hash(&self, k0: u64, k1: u64) -> u64 {
-        let mut s = State::new(k0, k1);
+        self.hash_with(&State::new(k0, k1))
+    }
+
+    #[inline]
+    fn hash_with<H: Hasher>(&self, hasher: &H) -> u64 {
+        let mut s = hasher.clone();
         self.hash_bytes(|bytes| s.write(bytes));
-        s.result()
+        s.finish()
     }
 }
 
@@ -152,7 +182,112 @@ impl<'a, A: HashBytes> HashBytes for &'a [A] {
     }
 }
 
-pub struct State {
+impl<'a> HashBytes for &'a str {
+    #[inline]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        self.len().hash_bytes(|x| f(x));
+        f(as_bytes(*self))
+    }
+}
+
+impl<A: HashBytes> HashBytes for Vec<A> {
+    #[inline(always)]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        self.as_slice().hash_bytes(f)
+    }
+}
+
+impl<A: HashBytes> HashBytes for Option<A> {
+    #[inline]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        match *self {
+            None => f([0u8]),
+            Some(ref x) => {
+                f([1u8]);
+                x.hash_bytes(|x| f(x))
+            }
+        }
+    }
+}
+
+impl<A: HashBytes> HashBytes for ~A {
+    #[inline(always)]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        (**self).hash_bytes(f)
+    }
+}
+
+impl<A: HashBytes> HashBytes for Rc<A> {
+    #[inline(always)]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        self.borrow().hash_bytes(f)
+    }
+}
+
+impl<A: HashBytes> HashBytes for Arc<A> {
+    #[inline(always)]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        self.borrow().hash_bytes(f)
+    }
+}
+
+macro_rules! tuple_hash_bytes(
+    ($($A:ident),+) => (
+        impl<$($A: HashBytes),+> HashBytes for ($($A,)+) {
+            #[inline]
+            #[allow(uppercase_variables)]
+            fn hash_bytes(&self, f: |&[u8]|) {
+                let ($(ref $A,)+) = *self;
+                $($A.hash_bytes(|x| f(x));)+
+            }
+        }
+    )
+)
+
+tuple_hash_bytes!(A)
+tuple_hash_bytes!(A, B)
+tuple_hash_bytes!(A, B, C)
+tuple_hash_bytes!(A, B, C, D)
+tuple_hash_bytes!(A, B, C, D, E)
+tuple_hash_bytes!(A, B, C, D, E, F)
+tuple_hash_bytes!(A, B, C, D, E, F, G)
+tuple_hash_bytes!(A, B, C, D, E, F, G, H)
+
+/// Floating point values are hashed by their bit pattern, after mapping -0.0 to 0.0 and every NaN
+/// to a single quiet NaN, so values comparing equal (other than NaN) hash the same.
+impl HashBytes for f32 {
+    #[inline]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        let bits: u32 = if *self == 0.0 {
+            0
+        } else if *self != *self {
+            0x7fc00000
+        } else {
+            unsafe { transmute(*self) }
+        };
+        bits.hash_bytes(f)
+    }
+}
+
+/// Floating point values are hashed by their bit pattern, after mapping -0.0 to 0.0 and every NaN
+/// to a single quiet NaN, so values comparing equal (other than NaN) hash the same.
+impl HashBytes for f64 {
+    #[inline]
+    fn hash_bytes(&self, f: |&[u8]|) {
+        let bits: u64 = if *self == 0.0 {
+            0
+        } else if *self != *self {
+            0x7ff8000000000000
+        } else {
+            unsafe { transmute(*self) }
+        };
+        bits.hash_bytes(f)
+    }
+}
+
+// The streaming state shared by the 64-bit SipHash variants, which differ only in the number of
+// rounds and in finalization
+struct Sip {
     k0: u64,
     k1: u64,
     length: uint, // how many bytes we've processed
@@ -176,11 +311,24 @@ macro_rules! u8to64_le (
      $buf[7+$i] as u64 << 56)
 )
 
+macro_rules! u8to32_le (
+    ($buf:expr, $i:expr) =>
+    ($buf[0+$i] as u32 |
+     $buf[1+$i] as u32 << 8 |
+     $buf[2+$i] as u32 << 16 |
+     $buf[3+$i] as u32 << 24)
+)
+
 macro_rules! rotl (
     ($x:expr, $b:expr) =>
     (($x << $b) | ($x >> (64 - $b)))
 )
 
+macro_rules! rotl32 (
+    ($x:expr, $b:expr) =>
+    (($x << $b) | ($x >> (32 - $b)))
+)
+
 macro_rules! compress (
     ($v0:expr, $v1:expr, $v2:expr, $v3:expr) =>
     ({
@@ -193,12 +341,47 @@ macro_rules! compress (
     })
 )
 
-impl State {
-    #[inline]
-    pub fn new(key0: u64, key1: u64) -> State {
-        let mut state = State {
-            k0: key0,
-            k1: key1,
+macro_rules! half_compress (
+    ($v0:expr, $v1:expr, $v2:expr, $v3:expr) =>
+    ({
+        $v0 += $v1; $v1 = rotl32!($v1, 5); $v1 ^= $v0;
+        $v0 = rotl32!($v0, 16);
+        $v2 += $v3; $v3 = rotl32!($v3, 8); $v3 ^= $v2;
+        $v0 += $v3; $v3 = rotl32!($v3, 7); $v3 ^= $v0;
+        $v2 += $v1; $v1 = rotl32!($v1, 13); $v1 ^= $v2;
+        $v2 = rotl32!($v2, 16);
+    })
+)
+
+// The round counts are always constants, so the loops are unrolled once inlined
+macro_rules! rounds (
+    ($n:expr, $v0:expr, $v1:expr, $v2:expr, $v3:expr) =>
+    ({
+        let mut r = 0u;
+        while r < $n {
+            compress!($v0, $v1, $v2, $v3);
+            r += 1;
+        }
+    })
+)
+
+macro_rules! half_rounds (
+    ($n:expr, $v0:expr, $v1:expr, $v2:expr, $v3:expr) =>
+    ({
+        let mut r = 0u;
+        while r < $n {
+            half_compress!($v0, $v1, $v2, $v3);
+            r += 1;
+        }
+    })
+)
+
+impl Sip {
+    #[inline(always)]
+    fn new(k0: u64, k1: u64, wide: bool) -> Sip {
+        let mut sip = Sip {
+            k0: k0,
+            k1: k1,
             length: 0,
             v0: 0,
             v1: 0,
@@ -207,43 +390,25 @@ impl State {
             tail: [ 0, 0, 0, 0, 0, 0, 0, 0 ],
             ntail: 0,
         };
-        state.reset();
-        state
+        sip.reset(wide);
+        sip
     }
 
-    #[inline]
-    pub fn result(&mut self) -> u64 {
-        let mut v0 = self.v0;
-        let mut v1 = self.v1;
-        let mut v2 = self.v2;
-        let mut v3 = self.v3;
-
-        let mut b : u64 = (self.length as u64 & 0xff) << 56;
-
-        if self.ntail > 0 { b |= self.tail[0] as u64 <<  0; }
-        if self.ntail > 1 { b |= self.tail[1] as u64 <<  8; }
-        if self.ntail > 2 { b |= self.tail[2] as u64 << 16; }
-        if self.ntail > 3 { b |= self.tail[3] as u64 << 24; }
-        if self.ntail > 4 { b |= self.tail[4] as u64 << 32; }
-        if self.ntail > 5 { b |= self.tail[5] as u64 << 40; }
-        if self.ntail > 6 { b |= self.tail[6] as u64 << 48; }
-
-        v3 ^= b;
-        compress!(v0, v1, v2, v3);
-        compress!(v0, v1, v2, v3);
-        v0 ^= b;
-
-        v2 ^= 0xff;
-        compress!(v0, v1, v2, v3);
-        compress!(v0, v1, v2, v3);
-        compress!(v0, v1, v2, v3);
-        compress!(v0, v1, v2, v3);
-
-        (v0 ^ v1 ^ v2 ^ v3)
+    #[inline(always)]
+    fn reset(&mut self, wide: bool) {
+        self.length = 0;
+        self.v0 = self.k0 ^ 0x736f6d6570736575;
+        self.v1 = self.k1 ^ 0x646f72616e646f6d;
+        self.v2 = self.k0 ^ 0x6c7967656e657261;
+        self.v3 = self.k1 ^ 0x7465646279746573;
+        self.ntail = 0;
+        if wide {
+            self.v1 ^= 0xee;
+        }
     }
 
-    #[inline]
-    pub fn write(&mut self, msg: &[u8]) {
+    #[inline(always)]
+    fn write(&mut self, msg: &[u8], c_rounds: uint) {
         let length = msg.len();
         self.length += length;
 
@@ -271,8 +436,7 @@ impl State {
             let m = u8to64_le!(self.tail, 0);
 
             self.v3 ^= m;
-            compress!(self.v0, self.v1, self.v2, self.v3);
-            compress!(self.v0, self.v1, self.v2, self.v3);
+            rounds!(c_rounds, self.v0, self.v1, self.v2, self.v3);
             self.v0 ^= m;
 
             self.ntail = 0;
@@ -288,8 +452,7 @@ impl State {
             let mi = u8to64_le!(msg, i);
 
             self.v3 ^= mi;
-            compress!(self.v0, self.v1, self.v2, self.v3);
-            compress!(self.v0, self.v1, self.v2, self.v3);
+            rounds!(c_rounds, self.v0, self.v1, self.v2, self.v3);
             self.v0 ^= mi;
 
             i += 8;
@@ -303,220 +466,728 @@ impl State {
         self.ntail = left;
     }
 
+    // Return a copy of the state with the final block, holding the tail and length, compressed
+    #[inline(always)]
+    fn last_block(&self, c_rounds: uint) -> Sip {
+        let mut s = *self;
 
-    #[inline]
-    pub fn reset(&mut self) {
-        self.length = 0;
-        self.v0 = self.k0 ^ 0x736f6d6570736575;
-        self.v1 = self.k1 ^ 0x646f72616e646f6d;
-        self.v2 = self.k0 ^ 0x6c7967656e657261;
-        self.v3 = self.k1 ^ 0x7465646279746573;
-        self.ntail = 0;
-    }
-}
-
-static INITIAL_CAPACITY: uint = 32u; // 2^5
+        let mut b : u64 = (self.length as u64 & 0xff) << 56;
 
-struct Bucket<K,V> {
-    hash: uint,
-    key: K,
-    value: V,
-}
+        if self.ntail > 0 { b |= self.tail[0] as u64 <<  0; }
+        if self.ntail > 1 { b |= self.tail[1] as u64 <<  8; }
+        if self.ntail > 2 { b |= self.tail[2] as u64 << 16; }
+        if self.ntail > 3 { b |= self.tail[3] as u64 << 24; }
+        if self.ntail > 4 { b |= self.tail[4] as u64 << 32; }
+        if self.ntail > 5 { b |= self.tail[5] as u64 << 40; }
+        if self.ntail > 6 { b |= self.tail[6] as u64 << 48; }
 
-pub struct HashMap<K,V> {
-    priv k0: u64,
-    priv k1: u64,
-    priv resize_at: uint,
-    priv size: uint,
-    priv buckets: Vec<Option<Bucket<K, V>>>
-}
+        s.v3 ^= b;
+        rounds!(c_rounds, s.v0, s.v1, s.v2, s.v3);
+        s.v0 ^= b;
+        s
+    }
 
-enum SearchResult {
-    FoundEntry(uint), FoundHole(uint), TableFull
-}
+    #[inline(always)]
+    fn result(&self, c_rounds: uint, d_rounds: uint) -> u64 {
+        let mut s = self.last_block(c_rounds);
 
-#[inline(always)]
-fn resize_at(capacity: uint) -> uint {
-    (capacity * 3) / 4
-}
+        s.v2 ^= 0xff;
+        rounds!(d_rounds, s.v0, s.v1, s.v2, s.v3);
 
-impl<K:Hash + Eq,V> HashMap<K, V> {
-    #[inline(always)]
-    fn to_bucket(&self, h: uint) -> uint {
-        h % self.buckets.len()
+        s.v0 ^ s.v1 ^ s.v2 ^ s.v3
     }
 
     #[inline(always)]
-    fn next_bucket(&self, idx: uint, len_buckets: uint) -> uint {
-        (idx + 1) % len_buckets
+    fn result128(&self, c_rounds: uint, d_rounds: uint) -> (u64, u64) {
+        let mut s = self.last_block(c_rounds);
+
+        s.v2 ^= 0xee;
+        rounds!(d_rounds, s.v0, s.v1, s.v2, s.v3);
+        let h0 = s.v0 ^ s.v1 ^ s.v2 ^ s.v3;
+
+        s.v1 ^= 0xdd;
+        rounds!(d_rounds, s.v0, s.v1, s.v2, s.v3);
+        let h1 = s.v0 ^ s.v1 ^ s.v2 ^ s.v3;
+
+        (h0, h1)
     }
+}
+
+/// SipHash-2-4, with a 64-bit output
+///
+/// This is the default hash function for hash tables, as it is keyed and resistant to
+/// deliberately colliding input.
+pub struct State {
+    priv sip: Sip
+}
 
+impl State {
     #[inline]
-    fn bucket_sequence(&self, hash: uint, op: |uint| -> bool) -> bool {
-        let start_idx = self.to_bucket(hash);
-        let len_buckets = self.buckets.len();
-        let mut idx = start_idx;
-        loop {
-            if !op(idx) { return false; }
-            idx = self.next_bucket(idx, len_buckets);
-            if idx == start_idx {
-                return true;
-            }
-        }
+    pub fn new(key0: u64, key1: u64) -> State {
+        State { sip: Sip::new(key0, key1, false) }
     }
 
     #[inline]
-    fn bucket_for_key(&self, k: &K) -> SearchResult {
-        let hash = k.hash(self.k0, self.k1) as uint;
-        self.bucket_for_key_with_hash(hash, k)
+    pub fn result(&self) -> u64 {
+        self.sip.result(2, 4)
     }
 
     #[inline]
-    fn bucket_for_key_with_hash(&self,
-                                hash: uint,
-                                k: &K)
-                             -> SearchResult {
-        let mut ret = TableFull;
-        self.bucket_sequence(hash, |i| {
-            match self.buckets.as_slice()[i] {
-                Some(ref bkt) if bkt.hash == hash && *k == bkt.key => {
-                    ret = FoundEntry(i); false
-                },
-                None => { ret = FoundHole(i); false }
-                _ => true,
-            }
-        });
-        ret
+    pub fn write(&mut self, msg: &[u8]) {
+        self.sip.write(msg, 2)
     }
 
-    /// Expand the capacity of the array to the next power of two
-    /// and re-insert each of the existing buckets.
     #[inline]
-    fn expand(&mut self) {
-        let new_capacity = self.buckets.len() * 2;
-        self.resize(new_capacity);
+    pub fn reset(&mut self) {
+        self.sip.reset(false)
     }
+}
 
-    /// Expands the capacity of the array and re-insert each of the
-    /// existing buckets.
-    fn resize(&mut self, new_capacity: uint) {
-        self.resize_at = resize_at(new_capacity);
+impl Clone for State {
+    #[inline(always)]
+    fn clone(&self) -> State { *self }
+}
 
-        let mut xs = Vec::with_capacity(new_capacity);
-        let mut i = 0;
-        while i < new_capacity {
-            xs.push(None);
-            i += 1;
-        }
+impl Hasher for State {
+    #[inline(always)]
+    fn write(&mut self, msg: &[u8]) { self.write(msg) }
 
-        let mut old_buckets = replace(&mut self.buckets, xs);
+    #[inline(always)]
+    fn finish(&self) -> u64 { self.result() }
+}
 
-        self.size = 0;
-        while !old_buckets.is_empty() {
-            self.insert_opt_bucket(old_buckets.pop().get());
-        }
-    }
+/// SipHash-1-3, with a 64-bit output
+///
+/// With half the compression rounds of SipHash-2-4, this is noticeably faster for short keys
+/// while still making collisions hard to find without the key. It suits tables that need some
+/// protection against hostile input but are dominated by hashing cost.
+pub struct Sip13State {
+    priv sip: Sip
+}
 
-    fn insert_opt_bucket(&mut self, bucket: Option<Bucket<K, V>>) {
-        match bucket {
-            Some(Bucket{hash: hash, key: key, value: value}) => {
-                self.insert_internal(hash, key, value);
-            }
-            None => {}
-        }
+impl Sip13State {
+    #[inline]
+    pub fn new(key0: u64, key1: u64) -> Sip13State {
+        Sip13State { sip: Sip::new(key0, key1, false) }
     }
 
     #[inline]
-    fn value_for_bucket<'a>(&'a self, idx: uint) -> &'a V {
-        match self.buckets.as_slice()[idx] {
-            Some(ref bkt) => &bkt.value,
-            None => abort()
-        }
+    pub fn result(&self) -> u64 {
+        self.sip.result(1, 3)
     }
 
     #[inline]
-    fn mut_value_for_bucket<'a>(&'a mut self, idx: uint) -> &'a mut V {
-        match self.buckets.as_mut_slice()[idx] {
-            Some(ref mut bkt) => &mut bkt.value,
-            None => abort()
-        }
+    pub fn write(&mut self, msg: &[u8]) {
+        self.sip.write(msg, 1)
     }
 
-    /// Inserts the key value pair into the buckets.
-    /// Assumes that there will be a bucket.
-    /// True if there was no previous entry with that key
-    fn insert_internal(&mut self, hash: uint, k: K, v: V) -> Option<V> {
-        match self.bucket_for_key_with_hash(hash, &k) {
-            TableFull => abort(),
-            FoundHole(idx) => {
-                self.buckets.as_mut_slice()[idx] = Some(Bucket{hash: hash, key: k,
-                                                        value: v});
-                self.size += 1;
-                None
-            }
-            FoundEntry(idx) => {
-                match self.buckets.as_mut_slice()[idx] {
-                    None => abort(),
-                    Some(ref mut b) => {
-                        b.hash = hash;
-                        b.key = k;
-                        Some(replace(&mut b.value, v))
-                    }
-                }
-            }
-        }
+    #[inline]
+    pub fn reset(&mut self) {
+        self.sip.reset(false)
     }
+}
 
-    fn pop_internal(&mut self, hash: uint, k: &K) -> Option<V> {
-        // Removing from an open-addressed hashtable
-        // is, well, painful.  The problem is that
-        // the entry may lie on the probe path for other
-        // entries, so removing it would make you think that
-        // those probe paths are empty.
-        //
-        // To address this we basically have to keep walking,
-        // re-inserting entries we find until we reach an empty
-        // bucket.  We know we will eventually reach one because
-        // we insert one ourselves at the beginning (the removed
-        // entry).
-        //
-        // I found this explanation elucidating:
-        // http://www.maths.lse.ac.uk/Courses/MA407/del-hash.pdf
-        let mut idx = match self.bucket_for_key_with_hash(hash, k) {
-            TableFull | FoundHole(_) => return None,
-            FoundEntry(idx) => idx
-        };
+impl Clone for Sip13State {
+    #[inline(always)]
+    fn clone(&self) -> Sip13State { *self }
+}
 
-        let len_buckets = self.buckets.len();
-        let bucket = self.buckets.as_mut_slice()[idx].take();
-        let value = bucket.map(|bucket| bucket.value);
-
-        /* re-inserting buckets may cause changes in size, so remember
-        what our new size is ahead of time before we start insertions */
-        let size = self.size - 1;
-        idx = self.next_bucket(idx, len_buckets);
-        while self.buckets.as_slice()[idx].is_some() {
-            let bucket = self.buckets.as_mut_slice()[idx].take();
-            self.insert_opt_bucket(bucket);
-            idx = self.next_bucket(idx, len_buckets);
-        }
-        self.size = size;
+impl Hasher for Sip13State {
+    #[inline(always)]
+    fn write(&mut self, msg: &[u8]) { self.write(msg) }
 
-        value
-    }
+    #[inline(always)]
+    fn finish(&self) -> u64 { self.result() }
 }
 
-impl<K:Hash + Eq,V> Container for HashMap<K, V> {
-    /// Return the number of elements in the map
-    fn len(&self) -> uint { self.size }
+/// SipHash-2-4, with a 128-bit output
+///
+/// The result is returned as the `(low, high)` halves, matching the byte order of the reference
+/// implementation. This is intended for fingerprinting content, where the chance of two distinct
+/// inputs colliding must stay negligible across very large numbers of values. As a `Hasher`, the
+/// two halves are folded together.
+pub struct Sip128State {
+    priv sip: Sip
 }
 
-impl<K: Hash + Eq, V> HashMap<K, V> {
-    /// Return a reference to the value corresponding to the key
-    pub fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
-        match self.bucket_for_key(k) {
-            FoundEntry(idx) => Some(self.value_for_bucket(idx)),
-            TableFull | FoundHole(_) => None,
+impl Sip128State {
+    #[inline]
+    pub fn new(key0: u64, key1: u64) -> Sip128State {
+        Sip128State { sip: Sip::new(key0, key1, true) }
+    }
+
+    #[inline]
+    pub fn result(&self) -> (u64, u64) {
+        self.sip.result128(2, 4)
+    }
+
+    #[inline]
+    pub fn write(&mut self, msg: &[u8]) {
+        self.sip.write(msg, 2)
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        self.sip.reset(true)
+    }
+}
+
+impl Clone for Sip128State {
+    #[inline(always)]
+    fn clone(&self) -> Sip128State { *self }
+}
+
+impl Hasher for Sip128State {
+    #[inline(always)]
+    fn write(&mut self, msg: &[u8]) { self.write(msg) }
+
+    #[inline(always)]
+    fn finish(&self) -> u64 {
+        let (h0, h1) = self.result();
+        h0 ^ h1
+    }
+}
+
+/// HalfSipHash-2-4, with a 32-bit output
+///
+/// HalfSipHash works on 32-bit words with a 64-bit key, so it is much cheaper than SipHash on
+/// 32-bit targets. The shorter key and output give a lower security margin, which is still
+/// sufficient for protecting hash tables against flooding.
+pub struct HalfSipState {
+    priv k0: u32,
+    priv k1: u32,
+    priv length: uint, // how many bytes we've processed
+    priv v0: u32,      // hash state
+    priv v1: u32,
+    priv v2: u32,
+    priv v3: u32,
+    priv tail: [u8, ..4], // unprocessed bytes
+    priv ntail: uint      // how many bytes in tail are valid
+}
+
+impl HalfSipState {
+    #[inline]
+    pub fn new(key0: u32, key1: u32) -> HalfSipState {
+        let mut state = HalfSipState {
+            k0: key0,
+            k1: key1,
+            length: 0,
+            v0: 0,
+            v1: 0,
+            v2: 0,
+            v3: 0,
+            tail: [ 0, 0, 0, 0 ],
+            ntail: 0,
+        };
+        state.reset();
+        state
+    }
+
+    #[inline]
+    pub fn result(&self) -> u32 {
+        let mut v0 = self.v0;
+        let mut v1 = self.v1;
+        let mut v2 = self.v2;
+        let mut v3 = self.v3;
+
+        let mut b : u32 = (self.length as u32 & 0xff) << 24;
+
+        if self.ntail > 0 { b |= self.tail[0] as u32 <<  0; }
+        if self.ntail > 1 { b |= self.tail[1] as u32 <<  8; }
+        if self.ntail > 2 { b |= self.tail[2] as u32 << 16; }
+
+        v3 ^= b;
+        half_rounds!(2, v0, v1, v2, v3);
+        v0 ^= b;
+
+        v2 ^= 0xff;
+        half_rounds!(4, v0, v1, v2, v3);
+
+        v1 ^ v3
+    }
+
+    #[inline]
+    pub fn write(&mut self, msg: &[u8]) {
+        let length = msg.len();
+        self.length += length;
+
+        let mut needed = 0u;
+
+        if self.ntail != 0 {
+            needed = 4 - self.ntail;
+
+            if length < needed {
+                let mut t = 0;
+                while t < length {
+                    self.tail[self.ntail+t] = msg[t];
+                    t += 1;
+                }
+                self.ntail += length;
+                return;
+            }
+
+            let mut t = 0;
+            while t < needed {
+                self.tail[self.ntail+t] = msg[t];
+                t += 1;
+            }
+
+            let m = u8to32_le!(self.tail, 0);
+
+            self.v3 ^= m;
+            half_rounds!(2, self.v0, self.v1, self.v2, self.v3);
+            self.v0 ^= m;
+
+            self.ntail = 0;
+        }
+
+        // Buffered tail is now flushed, process new input.
+        let len = length - needed;
+        let end = len & (!0x3);
+        let left = len & 0x3;
+
+        let mut i = needed;
+        while i < end {
+            let mi = u8to32_le!(msg, i);
+
+            self.v3 ^= mi;
+            half_rounds!(2, self.v0, self.v1, self.v2, self.v3);
+            self.v0 ^= mi;
+
+            i += 4;
+        }
+
+        let mut t = 0u;
+        while t < left {
+            self.tail[t] = msg[i+t];
+            t += 1
+        }
+        self.ntail = left;
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        self.length = 0;
+        self.v0 = self.k0;
+        self.v1 = self.k1;
+        self.v2 = self.k0 ^ 0x6c796765;
+        self.v3 = self.k1 ^ 0x74656462;
+        self.ntail = 0;
+    }
+}
+
+impl Clone for HalfSipState {
+    #[inline(always)]
+    fn clone(&self) -> HalfSipState { *self }
+}
+
+impl Hasher for HalfSipState {
+    #[inline(always)]
+    fn write(&mut self, msg: &[u8]) { self.write(msg) }
+
+    #[inline(always)]
+    fn finish(&self) -> u64 { self.result() as u64 }
+}
+
+/// The 64-bit FNV-1a hash function
+///
+/// FNV-1a is very fast for short keys, but offers no protection against deliberately colliding
+/// input, even when keyed. The keys only perturb the starting state: `k0` is folded into the offset
+/// basis and `k1` is then hashed as 8 bytes of input, so instances with different keys will
+/// distribute values differently, but inputs colliding under one key collide under every key.
+pub struct FnvState {
+    priv hash: u64
+}
+
+impl FnvState {
+    #[inline]
+    pub fn new(k0: u64, k1: u64) -> FnvState {
+        // Mix `k0` before folding in `k1`, so that equal bytes of the two keys do not cancel out
+        let mut hash = (0xcbf29ce484222325 ^ k0) * 0x100000001b3;
+        let mut i = 0;
+        while i < 64 {
+            hash ^= (k1 >> i) & 0xff;
+            hash *= 0x100000001b3;
+            i += 8;
+        }
+        FnvState { hash: hash }
+    }
+}
+
+impl Clone for FnvState {
+    #[inline(always)]
+    fn clone(&self) -> FnvState { *self }
+}
+
+impl Hasher for FnvState {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) {
+        let mut hash = self.hash;
+        for byte in iter(msg) {
+            hash ^= *byte as u64;
+            hash *= 0x100000001b3;
+        }
+        self.hash = hash;
+    }
+
+    #[inline(always)]
+    fn finish(&self) -> u64 { self.hash }
+}
+
+/// A fast multiplicative hash function consuming 64 bits of input at a time
+///
+/// This is intended for tables with integer keys that are not exposed to untrusted input, as it
+/// offers no protection against deliberately colliding input.
+pub struct MulState {
+    priv hash: u64
+}
+
+static MUL_SEED: u64 = 0x517cc1b727220a95;
+
+impl MulState {
+    #[inline]
+    pub fn new(k0: u64, k1: u64) -> MulState {
+        MulState { hash: k0 ^ rotl!(k1, 32) }
+    }
+
+    #[inline(always)]
+    fn add_word(&mut self, word: u64) {
+        self.hash = (rotl!(self.hash, 5) ^ word) * MUL_SEED;
+    }
+}
+
+impl Clone for MulState {
+    #[inline(always)]
+    fn clone(&self) -> MulState { *self }
+}
+
+impl Hasher for MulState {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) {
+        let len = msg.len();
+        let mut i = 0;
+        while i + 8 <= len {
+            self.add_word(u8to64_le!(msg, i));
+            i += 8;
+        }
+        while i < len {
+            self.add_word(msg[i] as u64);
+            i += 1;
+        }
+    }
+
+    /// The product is folded, because the low bits of the multiplication only depend on the low
+    /// bits of the input and hash tables index by the low bits.
+    #[inline(always)]
+    fn finish(&self) -> u64 { self.hash ^ (self.hash >> 32) }
+}
+
+static KEYS_UNINIT: uint = 0;
+static KEYS_PENDING: uint = 1;
+static KEYS_READY: uint = 2;
+
+static mut KEY_STATE: uint = KEYS_UNINIT;
+static mut KEY_BASE_0: u64 = 0;
+static mut KEY_BASE_1: u64 = 0;
+static mut KEY_COUNTER: u64 = 0;
+
+/// Return a random 128-bit key for a new hash table.
+///
+/// A base key is drawn from `entropy::fill` the first time this is called. Each call returns the
+/// base key with the first half offset by a counter, so each table gets a distinct key without
+/// asking the operating system for more entropy.
+pub fn random_keys() -> (u64, u64) {
+    unsafe {
+        if atomic_load_acq(&KEY_STATE) != KEYS_READY {
+            if atomic_cxchg(&mut KEY_STATE, KEYS_UNINIT, KEYS_PENDING) == KEYS_UNINIT {
+                let mut buf = [0u8, ..16];
+                fill(buf);
+                KEY_BASE_0 = u8to64_le!(buf, 0);
+                KEY_BASE_1 = u8to64_le!(buf, 8);
+                atomic_store_rel(&mut KEY_STATE, KEYS_READY);
+            } else {
+                while atomic_load_acq(&KEY_STATE) != KEYS_READY {
+                    deschedule()
+                }
+            }
+        }
+        let n = atomic_xadd_relaxed(&mut KEY_COUNTER, 1);
+        (KEY_BASE_0 + n, KEY_BASE_1)
+    }
+}
+
+static INITIAL_CAPACITY: uint = 32u; // 2^5
+
+struct Bucket<K,V> {
+    hash: uint,
+    key: K,
+    value: V,
+}
+
+/// A hash table using open addressing with Robin Hood hashing.
+///
+/// On insertion, an entry further from its ideal bucket steals the slot of an entry closer to its
+/// own ideal bucket. This keeps the variance of probe lengths low, and lets a lookup stop as soon
+/// as it reaches an entry closer to home than the key being searched for would be. Removal shifts
+/// the following entries of the cluster back by one slot rather than leaving tombstones or
+/// re-inserting them.
+pub struct HashMap<K, V, H = State> {
+    priv hasher: H,
+    priv resize_at: uint,
+    priv size: uint,
+    priv buckets: Vec<Option<Bucket<K, V>>>
+}
+
+enum SearchResult {
+    /// The key is present at the index
+    FoundEntry(uint),
+    /// The key is absent, and belongs at the index with the given probe distance. The bucket may
+    /// be occupied by an entry closer to its ideal bucket, which will have to be displaced.
+    FoundHole(uint, uint),
+    TableFull
+}
+
+#[inline(always)]
+fn resize_at(capacity: uint) -> uint {
+    (capacity * 9) / 10
+}
+
+/// Return the distance of the bucket at `idx` from the ideal bucket for `hash`. The number of
+/// buckets must be a power of two.
+#[inline(always)]
+fn probe_distance(len_buckets: uint, hash: uint, idx: uint) -> uint {
+    let mask = len_buckets - 1;
+    (idx + len_buckets - (hash & mask)) & mask
+}
+
+impl<K: Hash + Eq, V, H: Hasher> HashMap<K, V, H> {
+    #[inline(always)]
+    fn hash_key(&self, k: &K) -> uint {
+        k.hash_with(&self.hasher) as uint
+    }
+
+    #[inline(always)]
+    fn to_bucket(&self, h: uint) -> uint {
+        h & (self.buckets.len() - 1)
+    }
+
+    #[inline(always)]
+    fn next_bucket(&self, idx: uint, len_buckets: uint) -> uint {
+        (idx + 1) & (len_buckets - 1)
+    }
+
+    #[inline]
+    fn bucket_for_key(&self, k: &K) -> SearchResult {
+        let hash = self.hash_key(k);
+        self.bucket_for_key_with_hash(hash, k)
+    }
+
+    #[inline]
+    fn bucket_for_key_with_hash(&self,
+                                hash: uint,
+                                k: &K)
+                             -> SearchResult {
+        self.search_hashed(hash, |key| *k == *key)
+    }
+
+    #[inline]
+    fn bucket_for_key_equiv<Q: Hash + Equiv<K>>(&self, k: &Q) -> SearchResult {
+        let hash = k.hash_with(&self.hasher) as uint;
+        self.search_hashed(hash, |key| k.equiv(key))
+    }
+
+    #[inline]
+    fn search_hashed(&self, hash: uint, is_match: |&K| -> bool) -> SearchResult {
+        let len_buckets = self.buckets.len();
+        let mut idx = self.to_bucket(hash);
+        let mut dist = 0;
+        while dist < len_buckets {
+            match self.buckets.as_slice()[idx] {
+                None => return FoundHole(idx, dist),
+                Some(ref bkt) => {
+                    if bkt.hash == hash && is_match(&bkt.key) {
+                        return FoundEntry(idx)
+                    }
+                    // The key would have displaced this entry if it was present.
+                    if probe_distance(len_buckets, bkt.hash, idx) < dist {
+                        return FoundHole(idx, dist)
+                    }
+                }
+            }
+            idx = self.next_bucket(idx, len_buckets);
+            dist += 1;
+        }
+        TableFull
+    }
+
+    /// Expand the capacity of the array to the next power of two
+    /// and re-insert each of the existing buckets.
+    #[inline]
+    fn expand(&mut self) {
+        let new_capacity = self.buckets.len() * 2;
+        self.resize(new_capacity);
+    }
+
+    /// Change the capacity of the array to `new_capacity`, which must be a power of two, and
+    /// re-insert each of the existing buckets.
+    fn resize(&mut self, new_capacity: uint) {
+        self.resize_at = resize_at(new_capacity);
+
+        let mut xs = Vec::with_capacity(new_capacity);
+        let mut i = 0;
+        while i < new_capacity {
+            xs.push(None);
+            i += 1;
+        }
+
+        let old_buckets = replace(&mut self.buckets, xs);
+
+        self.size = 0;
+        for bucket in old_buckets.move_iter() {
+            match bucket {
+                Some(bkt) => {
+                    // The keys are known to be distinct, so the search can be skipped.
+                    let idx = self.to_bucket(bkt.hash);
+                    self.robin_hood(idx, 0, bkt);
+                }
+                None => {}
+            }
+        }
+    }
+
+    #[inline]
+    fn value_for_bucket<'a>(&'a self, idx: uint) -> &'a V {
+        match self.buckets.as_slice()[idx] {
+            Some(ref bkt) => &bkt.value,
+            None => abort()
+        }
+    }
+
+    #[inline]
+    fn mut_value_for_bucket<'a>(&'a mut self, idx: uint) -> &'a mut V {
+        match self.buckets.as_mut_slice()[idx] {
+            Some(ref mut bkt) => &mut bkt.value,
+            None => abort()
+        }
+    }
+
+    /// Place `bucket` at `idx`, where it has the probe distance `dist`. Any entry already there is
+    /// displaced, and continues probing forward for a slot of its own.
+    fn robin_hood(&mut self, mut idx: uint, mut dist: uint, bucket: Bucket<K, V>) {
+        let len_buckets = self.buckets.len();
+        let mut bucket = bucket;
+        loop {
+            {
+                let slot = &mut self.buckets.as_mut_slice()[idx];
+                if slot.is_none() {
+                    *slot = Some(bucket);
+                    self.size += 1;
+                    return
+                }
+                match *slot {
+                    Some(ref mut bkt) => {
+                        let existing = probe_distance(len_buckets, bkt.hash, idx);
+                        if existing < dist {
+                            swap(bkt, &mut bucket);
+                            dist = existing;
+                        }
+                    }
+                    None => abort()
+                }
+            }
+            idx = self.next_bucket(idx, len_buckets);
+            dist += 1;
+        }
+    }
+
+    /// Inserts the key value pair into the buckets.
+    /// Assumes that there will be a bucket.
+    /// True if there was no previous entry with that key
+    fn insert_internal(&mut self, hash: uint, k: K, v: V) -> Option<V> {
+        match self.bucket_for_key_with_hash(hash, &k) {
+            TableFull => abort(),
+            FoundHole(idx, dist) => {
+                self.robin_hood(idx, dist, Bucket{hash: hash, key: k, value: v});
+                None
+            }
+            FoundEntry(idx) => {
+                match self.buckets.as_mut_slice()[idx] {
+                    None => abort(),
+                    Some(ref mut b) => {
+                        b.hash = hash;
+                        b.key = k;
+                        Some(replace(&mut b.value, v))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove the entry at `idx`, shifting the rest of the cluster back by one slot. Entries
+    /// already in their ideal bucket terminate the cluster, as moving them back would place them
+    /// before the start of their probe sequence.
+    fn pop_bucket(&mut self, mut idx: uint) -> Bucket<K, V> {
+        let len_buckets = self.buckets.len();
+        let bucket = self.buckets.as_mut_slice()[idx].take().get();
+        self.size -= 1;
+
+        loop {
+            let next = self.next_bucket(idx, len_buckets);
+            let shift = match self.buckets.as_slice()[next] {
+                Some(ref bkt) => probe_distance(len_buckets, bkt.hash, next) != 0,
+                None => false
+            };
+            if !shift {
+                break
+            }
+            let moved = self.buckets.as_mut_slice()[next].take();
+            self.buckets.as_mut_slice()[idx] = moved;
+            idx = next;
+        }
+
+        bucket
+    }
+
+    fn pop_internal(&mut self, hash: uint, k: &K) -> Option<V> {
+        match self.bucket_for_key_with_hash(hash, k) {
+            TableFull | FoundHole(..) => None,
+            FoundEntry(idx) => Some(self.pop_bucket(idx).value)
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, H: Hasher> Container for HashMap<K, V, H> {
+    /// Return the number of elements in the map
+    fn len(&self) -> uint { self.size }
+}
+
+impl<K: Hash + Eq, V> HashMap<K, V> {
+    /// Create an empty `HashMap` using SipHash-2-4 with a random key.
+    #[inline]
+    pub fn new() -> HashMap<K, V> {
+        HashMap::with_capacity(0)
+    }
+
+    /// Create an empty `HashMap` using SipHash-2-4 with a random key, with space for at least
+    /// `capacity` elements.
+    pub fn with_capacity(capacity: uint) -> HashMap<K, V> {
+        let (k0, k1) = random_keys();
+        HashMap::with_capacity_and_keys(k0, k1, capacity)
+    }
+
+    /// Create an empty `HashMap` using SipHash-2-4 with the specified 128-bit key (`k0` and `k1`),
+    /// with space for at least `capacity` elements.
+    pub fn with_capacity_and_keys(k0: u64, k1: u64, capacity: uint) -> HashMap<K, V> {
+        HashMap::with_capacity_and_hasher(State::new(k0, k1), capacity)
+    }
+}
+
+impl<K: Hash + Eq, V, H: Hasher> HashMap<K, V, H> {
+    /// Return a reference to the value corresponding to the key
+    pub fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
+        match self.bucket_for_key(k) {
+            FoundEntry(idx) => Some(self.value_for_bucket(idx)),
+            TableFull | FoundHole(..) => None,
         }
     }
 
@@ -524,11 +1195,48 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn find_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
         let idx = match self.bucket_for_key(k) {
             FoundEntry(idx) => idx,
-            TableFull | FoundHole(_) => return None
+            TableFull | FoundHole(..) => return None
+        };
+        Some(self.mut_value_for_bucket(idx))
+    }
+
+    /// Return true if the map contains a value for the key
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.find(k).is_some()
+    }
+
+    /// Return a reference to the value corresponding to a key equivalent to `k`. The borrowed
+    /// form must hash identically to the owned key for the lookup to succeed.
+    pub fn find_equiv<'a, Q: Hash + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V> {
+        match self.bucket_for_key_equiv(k) {
+            FoundEntry(idx) => Some(self.value_for_bucket(idx)),
+            TableFull | FoundHole(..) => None,
+        }
+    }
+
+    /// Return a mutable reference to the value corresponding to a key equivalent to `k`
+    pub fn find_mut_equiv<'a, Q: Hash + Equiv<K>>(&'a mut self, k: &Q) -> Option<&'a mut V> {
+        let idx = match self.bucket_for_key_equiv(k) {
+            FoundEntry(idx) => idx,
+            TableFull | FoundHole(..) => return None
         };
         Some(self.mut_value_for_bucket(idx))
     }
 
+    /// Return true if the map contains a value for a key equivalent to `k`
+    pub fn contains_key_equiv<Q: Hash + Equiv<K>>(&self, k: &Q) -> bool {
+        self.find_equiv(k).is_some()
+    }
+
+    /// Remove the key equivalent to `k` from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn pop_equiv<Q: Hash + Equiv<K>>(&mut self, k: &Q) -> Option<V> {
+        match self.bucket_for_key_equiv(k) {
+            FoundEntry(idx) => Some(self.pop_bucket(idx).value),
+            TableFull | FoundHole(..) => None
+        }
+    }
+
     /// Insert a key-value pair from the map. If the key already had a value
     /// present in the map, that value is returned. Otherwise None is returned.
     pub fn swap(&mut self, k: K, v: V) -> Option<V> {
@@ -544,19 +1252,21 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             self.expand();
         }
 
-        let hash = k.hash(self.k0, self.k1) as uint;
+        let hash = self.hash_key(&k);
         self.insert_internal(hash, k, v)
     }
 
     /// Removes a key from the map, returning the value at the key if the key
     /// was previously in the map.
     pub fn pop(&mut self, k: &K) -> Option<V> {
-        let hash = k.hash(self.k0, self.k1) as uint;
+        let hash = self.hash_key(k);
         self.pop_internal(hash, k)
     }
 
-    pub fn with_capacity_and_keys(k0: u64, k1: u64, capacity: uint) -> HashMap<K, V> {
-        let capacity = max(INITIAL_CAPACITY, capacity);
+    /// Create an empty `HashMap` using `hasher` as the hash function, with space for at least
+    /// `capacity` elements.
+    pub fn with_capacity_and_hasher(hasher: H, capacity: uint) -> HashMap<K, V, H> {
+        let capacity = next_power_of_two(max(INITIAL_CAPACITY, capacity * 10 / 9 + 1));
         let mut xs = Vec::with_capacity(capacity);
         let mut i = 0;
         while i < capacity {
@@ -565,7 +1275,7 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         }
 
         HashMap {
-            k0: k0, k1: k1,
+            hasher: hasher,
             resize_at: resize_at(capacity),
             size: 0,
             buckets: xs
@@ -574,22 +1284,67 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
 
     /// Reserve space for at least `n` elements in the hash table.
     pub fn reserve_at_least(&mut self, n: uint) {
-        if n > self.buckets.len() {
-            let buckets = n * 4 / 3 + 1;
+        if n > self.resize_at {
+            let buckets = n * 10 / 9 + 1;
             self.resize(next_power_of_two(buckets));
         }
     }
 
-    /// Modify and return the value corresponding to the key in the map, or
-    /// insert and return a new value if it doesn't exist.
-    pub fn mangle<'a,
-                  A>(
-                  &'a mut self,
-                  k: K,
-                  a: A,
-                  not_found: |&K, A| -> V,
-                  found: |&K, &mut V, A|)
-                  -> &'a mut V {
+    /// Shrink the capacity of the hash table as much as possible, while keeping space for its
+    /// current elements.
+    pub fn shrink_to_fit(&mut self) {
+        let n = self.size;
+        self.shrink_to(n);
+    }
+
+    /// Shrink the capacity of the hash table as much as possible, while keeping space for at
+    /// least `n` elements and its current elements. The buckets are rehashed into a smaller
+    /// array, which is never less than the initial capacity.
+    pub fn shrink_to(&mut self, n: uint) {
+        let n = max(n, self.size);
+        let buckets = next_power_of_two(max(INITIAL_CAPACITY, n * 10 / 9 + 1));
+        if buckets < self.buckets.len() {
+            self.resize(buckets);
+        }
+    }
+
+    /// Retain only the entries for which `f` returns true, visiting each entry once in arbitrary
+    /// order.
+    pub fn retain(&mut self, f: |&K, &mut V| -> bool) {
+        let len_buckets = self.buckets.len();
+
+        // Walk backwards from the bucket before the start of a cluster. Removing an entry shifts
+        // back the entries following it, which have all been visited already.
+        let mut head = 0;
+        loop {
+            match self.buckets.as_slice()[head] {
+                Some(ref bkt) if probe_distance(len_buckets, bkt.hash, head) != 0 => {}
+                _ => break
+            }
+            head += 1;
+        }
+
+        let mut idx = head;
+        let mut remaining = self.size;
+        while remaining > 0 {
+            idx = (idx + len_buckets - 1) & (len_buckets - 1);
+            let keep = match self.buckets.as_mut_slice()[idx] {
+                Some(ref mut bkt) => {
+                    remaining -= 1;
+                    f(&bkt.key, &mut bkt.value)
+                }
+                None => true
+            };
+            if !keep {
+                self.pop_bucket(idx);
+            }
+        }
+    }
+
+    /// Look up the key in the map, returning a view of its entry for in-place inspection, insertion,
+    /// modification or removal. The key is hashed once and the table is probed once, regardless of
+    /// the operations performed on the entry.
+    pub fn entry<'a>(&'a mut self, k: K) -> Entry<'a, K, V, H> {
         if self.size >= self.resize_at {
             // n.b.: We could also do this after searching, so
             // that we do not resize if this call to insert is
@@ -600,32 +1355,36 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             self.expand();
         }
 
-        let hash = k.hash(self.k0, self.k1) as uint;
-        let idx = match self.bucket_for_key_with_hash(hash, &k) {
+        let hash = self.hash_key(&k);
+        let result = self.bucket_for_key_with_hash(hash, &k);
+        match result {
             TableFull => abort(),
-            FoundEntry(idx) => { found(&k, self.mut_value_for_bucket(idx), a); idx }
-            FoundHole(idx) => {
-                let v = not_found(&k, a);
-                self.buckets.as_mut_slice()[idx] = Some(Bucket{hash: hash, key: k, value: v});
-                self.size += 1;
-                idx
-            }
-        };
-
-        self.mut_value_for_bucket(idx)
+            FoundEntry(idx) => Occupied(OccupiedEntry { map: self, idx: idx }),
+            FoundHole(idx, dist) => Vacant(VacantEntry { map: self, hash: hash, key: k, idx: idx,
+                                                         dist: dist })
+        }
     }
 
     /// Return the value corresponding to the key in the map, or insert
     /// and return the value if it doesn't exist.
     pub fn find_or_insert<'a>(&'a mut self, k: K, v: V) -> &'a mut V {
-        self.mangle(k, v, |_k, a| a, |_k,_v,_a| ())
+        match self.entry(k) {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => entry.set(v)
+        }
     }
 
     /// Return the value corresponding to the key in the map, or create,
     /// insert, and return a new value if it doesn't exist.
     pub fn find_or_insert_with<'a>(&'a mut self, k: K, f: |&K| -> V)
                                -> &'a mut V {
-        self.mangle(k, (), |k,_a| f(k), |_k,_v,_a| ())
+        match self.entry(k) {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => {
+                let v = f(entry.key());
+                entry.set(v)
+            }
+        }
     }
 
     /// Insert a key-value pair into the map if the key is not already present.
@@ -637,7 +1396,292 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                                  v: V,
                                  f: |&K, &mut V|)
                                  -> &'a mut V {
-        self.mangle(k, v, |_k,a| a, |k,v,_a| f(k,v))
+        match self.entry(k) {
+            Occupied(mut entry) => {
+                match entry.map.buckets.as_mut_slice()[entry.idx] {
+                    Some(ref mut bkt) => f(&bkt.key, &mut bkt.value),
+                    None => abort()
+                }
+                entry.into_mut()
+            }
+            Vacant(entry) => entry.set(v)
+        }
+    }
+}
+
+/// A view into a single key of a `HashMap`, obtained with `HashMap::entry`
+pub enum Entry<'a, K, V, H = State> {
+    /// The key is present in the map
+    Occupied(OccupiedEntry<'a, K, V, H>),
+    /// The key is absent from the map
+    Vacant(VacantEntry<'a, K, V, H>)
+}
+
+/// A view into a key present in a `HashMap`
+pub struct OccupiedEntry<'a, K, V, H = State> {
+    priv map: &'a mut HashMap<K, V, H>,
+    priv idx: uint
+}
+
+/// A view into a key absent from a `HashMap`, holding the key until a value is inserted
+pub struct VacantEntry<'a, K, V, H = State> {
+    priv map: &'a mut HashMap<K, V, H>,
+    priv hash: uint,
+    priv key: K,
+    priv idx: uint,
+    priv dist: uint
+}
+
+impl<'a, K: Hash + Eq, V, H: Hasher> OccupiedEntry<'a, K, V, H> {
+    /// Return a reference to the key in the map
+    pub fn key<'b>(&'b self) -> &'b K {
+        match self.map.buckets.as_slice()[self.idx] {
+            Some(ref bkt) => &bkt.key,
+            None => abort()
+        }
+    }
+
+    /// Return a reference to the value
+    pub fn get<'b>(&'b self) -> &'b V {
+        self.map.value_for_bucket(self.idx)
+    }
+
+    /// Return a mutable reference to the value
+    pub fn get_mut<'b>(&'b mut self) -> &'b mut V {
+        self.map.mut_value_for_bucket(self.idx)
+    }
+
+    /// Convert the entry into a mutable reference to the value, with the lifetime of the map
+    pub fn into_mut(self) -> &'a mut V {
+        let OccupiedEntry { map: map, idx: idx } = self;
+        map.mut_value_for_bucket(idx)
+    }
+
+    /// Replace the value, returning the old value
+    pub fn set(&mut self, v: V) -> V {
+        replace(self.get_mut(), v)
+    }
+
+    /// Remove the entry from the map, returning the value
+    pub fn take(self) -> V {
+        let OccupiedEntry { map: map, idx: idx } = self;
+        map.pop_bucket(idx).value
+    }
+}
+
+impl<'a, K: Hash + Eq, V, H: Hasher> VacantEntry<'a, K, V, H> {
+    /// Return a reference to the key that would be inserted
+    pub fn key<'b>(&'b self) -> &'b K {
+        &self.key
+    }
+
+    /// Insert the key with the value `v`, returning a mutable reference to the value with the
+    /// lifetime of the map
+    pub fn set(self, v: V) -> &'a mut V {
+        let VacantEntry { map: map, hash: hash, key: key, idx: idx, dist: dist } = self;
+        map.robin_hood(idx, dist, Bucket{hash: hash, key: key, value: v});
+        map.mut_value_for_bucket(idx)
+    }
+}
+
+impl<K, V, H> HashMap<K, V, H> {
+    /// The number of elements the map can hold without reallocating
+    #[inline]
+    pub fn capacity(&self) -> uint {
+        self.resize_at
+    }
+
+    /// Remove all entries from the map, keeping the allocated buckets
+    pub fn clear(&mut self) {
+        for bucket in mut_iter(self.buckets.as_mut_slice()) {
+            *bucket = None;
+        }
+        self.size = 0;
+    }
+
+    /// Remove all entries from the map, returning them in arbitrary order through an iterator.
+    /// The allocated buckets are kept. Any entries not consumed by the iterator are dropped with
+    /// it.
+    pub fn drain<'a>(&'a mut self) -> Drain<'a, K, V> {
+        let remaining = replace(&mut self.size, 0);
+        Drain { iter: mut_iter(self.buckets.as_mut_slice()), remaining: remaining }
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order
+    pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+        Entries { iter: iter(self.buckets.as_slice()), remaining: self.size }
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order, with mutable references to the
+    /// values
+    pub fn mut_iter<'a>(&'a mut self) -> MutEntries<'a, K, V> {
+        let remaining = self.size;
+        MutEntries { iter: mut_iter(self.buckets.as_mut_slice()), remaining: remaining }
+    }
+
+    /// A consuming iterator visiting all key-value pairs in arbitrary order
+    pub fn move_iter(self) -> MoveEntries<K, V> {
+        let remaining = self.size;
+        MoveEntries { iter: self.buckets.move_iter(), remaining: remaining }
+    }
+
+    /// An iterator visiting all keys in arbitrary order
+    pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
+        Keys { iter: self.iter() }
+    }
+
+    /// An iterator visiting all values in arbitrary order
+    pub fn values<'a>(&'a self) -> Values<'a, K, V> {
+        Values { iter: self.iter() }
+    }
+}
+
+/// An iterator over the key-value pairs of a `HashMap`
+pub struct Entries<'a, K, V> {
+    priv iter: Items<'a, Option<Bucket<K, V>>>,
+    priv remaining: uint
+}
+
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        for bucket in self.iter {
+            match *bucket {
+                Some(ref bkt) => {
+                    self.remaining -= 1;
+                    return Some((&bkt.key, &bkt.value))
+                }
+                None => {}
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// An iterator over the key-value pairs of a `HashMap`, with mutable references to the values
+pub struct MutEntries<'a, K, V> {
+    priv iter: MutItems<'a, Option<Bucket<K, V>>>,
+    priv remaining: uint
+}
+
+impl<'a, K, V> Iterator<(&'a K, &'a mut V)> for MutEntries<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
+        for bucket in self.iter {
+            match *bucket {
+                Some(ref mut bkt) => {
+                    self.remaining -= 1;
+                    return Some((&bkt.key, &mut bkt.value))
+                }
+                None => {}
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// A consuming iterator over the key-value pairs of a `HashMap`
+pub struct MoveEntries<K, V> {
+    priv iter: MoveItems<Option<Bucket<K, V>>>,
+    priv remaining: uint
+}
+
+impl<K, V> Iterator<(K, V)> for MoveEntries<K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<(K, V)> {
+        for bucket in self.iter {
+            match bucket {
+                Some(Bucket{key: key, value: value, ..}) => {
+                    self.remaining -= 1;
+                    return Some((key, value))
+                }
+                None => {}
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// A draining iterator over the key-value pairs of a `HashMap`
+pub struct Drain<'a, K, V> {
+    priv iter: MutItems<'a, Option<Bucket<K, V>>>,
+    priv remaining: uint
+}
+
+impl<'a, K, V> Iterator<(K, V)> for Drain<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<(K, V)> {
+        for bucket in self.iter {
+            match bucket.take() {
+                Some(Bucket{key: key, value: value, ..}) => {
+                    self.remaining -= 1;
+                    return Some((key, value))
+                }
+                None => {}
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[unsafe_destructor]
+impl<'a, K, V> Drop for Drain<'a, K, V> {
+    fn drop(&mut self) {
+        while self.next().is_some() {}
+    }
+}
+
+/// An iterator over the keys of a `HashMap`
+pub struct Keys<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a K> for Keys<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        self.iter.size_hint()
+    }
+}
+
+/// An iterator over the values of a `HashMap`
+pub struct Values<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a V> for Values<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a V> {
+        self.iter.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        self.iter.size_hint()
     }
 }
 
@@ -648,3 +1692,209 @@ fn next_power_of_two(n: uint) -> uint {
     while shift <= halfbits { tmp |= tmp >> shift; shift <<= 1u; }
     tmp + 1u
 }
+
+/// A hash set implemented as a `HashMap` where the value is `()`
+pub struct HashSet<T, H = State> {
+    priv map: HashMap<T, (), H>
+}
+
+impl<T: Hash + Eq, H: Hasher> Container for HashSet<T, H> {
+    /// Return the number of elements in the set
+    fn len(&self) -> uint { self.map.len() }
+}
+
+impl<T: Hash + Eq, H: Hasher> Eq for HashSet<T, H> {
+    fn eq(&self, other: &HashSet<T, H>) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+}
+
+impl<T: Hash + Eq> HashSet<T> {
+    /// Create an empty `HashSet` using SipHash-2-4 with a random key.
+    #[inline]
+    pub fn new() -> HashSet<T> {
+        HashSet { map: HashMap::new() }
+    }
+
+    /// Create an empty `HashSet` using SipHash-2-4 with a random key, with space for at least
+    /// `capacity` elements.
+    pub fn with_capacity(capacity: uint) -> HashSet<T> {
+        HashSet { map: HashMap::with_capacity(capacity) }
+    }
+
+    /// Create an empty `HashSet` using SipHash-2-4 with the specified 128-bit key (`k0` and `k1`),
+    /// with space for at least `capacity` elements.
+    pub fn with_capacity_and_keys(k0: u64, k1: u64, capacity: uint) -> HashSet<T> {
+        HashSet { map: HashMap::with_capacity_and_keys(k0, k1, capacity) }
+    }
+}
+
+impl<T: Hash + Eq, H: Hasher> HashSet<T, H> {
+    /// Create an empty `HashSet` using `hasher` as the hash function, with space for at least
+    /// `capacity` elements.
+    pub fn with_capacity_and_hasher(hasher: H, capacity: uint) -> HashSet<T, H> {
+        HashSet { map: HashMap::with_capacity_and_hasher(hasher, capacity) }
+    }
+
+    /// Reserve space for at least `n` elements in the hash table.
+    pub fn reserve_at_least(&mut self, n: uint) {
+        self.map.reserve_at_least(n)
+    }
+
+    /// Add a value to the set. Return true if the value was not already present in the set.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.swap(value, ()).is_none()
+    }
+
+    /// Return true if the set contains a value
+    pub fn contains(&self, value: &T) -> bool {
+        self.map.find(value).is_some()
+    }
+
+    /// Remove a value from the set. Return true if the value was present in the set.
+    pub fn remove(&mut self, value: &T) -> bool {
+        self.map.pop(value).is_some()
+    }
+
+    /// Return true if the set has no elements in common with `other`
+    pub fn is_disjoint(&self, other: &HashSet<T, H>) -> bool {
+        for v in self.iter() {
+            if other.contains(v) { return false }
+        }
+        true
+    }
+
+    /// Return true if the set is a subset of `other`
+    pub fn is_subset(&self, other: &HashSet<T, H>) -> bool {
+        if self.len() > other.len() { return false }
+        for v in self.iter() {
+            if !other.contains(v) { return false }
+        }
+        true
+    }
+
+    /// Return true if the set is a superset of `other`
+    #[inline]
+    pub fn is_superset(&self, other: &HashSet<T, H>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// An iterator visiting all elements in arbitrary order
+    pub fn iter<'a>(&'a self) -> SetItems<'a, T> {
+        self.map.keys()
+    }
+
+    /// Visit the values representing the difference, in arbitrary order
+    pub fn difference<'a>(&'a self, other: &'a HashSet<T, H>) -> Difference<'a, T, H> {
+        Difference { iter: self.iter(), other: other }
+    }
+
+    /// Visit the values representing the symmetric difference, in arbitrary order
+    pub fn symmetric_difference<'a>(&'a self, other: &'a HashSet<T, H>)
+                                    -> SymmetricDifference<'a, T, H> {
+        SymmetricDifference { a: self.difference(other), b: other.difference(self) }
+    }
+
+    /// Visit the values representing the intersection, in arbitrary order
+    pub fn intersection<'a>(&'a self, other: &'a HashSet<T, H>) -> Intersection<'a, T, H> {
+        Intersection { iter: self.iter(), other: other }
+    }
+
+    /// Visit the values representing the union, in arbitrary order
+    pub fn union<'a>(&'a self, other: &'a HashSet<T, H>) -> Union<'a, T, H> {
+        Union { a: self.iter(), b: other.difference(self) }
+    }
+}
+
+/// An iterator over the elements of a `HashSet`
+pub type SetItems<'a, T> = Keys<'a, T, ()>;
+
+/// An iterator over the values of one set that are not in another
+pub struct Difference<'a, T, H = State> {
+    priv iter: SetItems<'a, T>,
+    priv other: &'a HashSet<T, H>
+}
+
+impl<'a, T: Hash + Eq, H: Hasher> Iterator<&'a T> for Difference<'a, T, H> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        for v in self.iter {
+            if !self.other.contains(v) { return Some(v) }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator over the values present in both of two sets
+pub struct Intersection<'a, T, H = State> {
+    priv iter: SetItems<'a, T>,
+    priv other: &'a HashSet<T, H>
+}
+
+impl<'a, T: Hash + Eq, H: Hasher> Iterator<&'a T> for Intersection<'a, T, H> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        for v in self.iter {
+            if self.other.contains(v) { return Some(v) }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator over the values present in exactly one of two sets
+pub struct SymmetricDifference<'a, T, H = State> {
+    priv a: Difference<'a, T, H>,
+    priv b: Difference<'a, T, H>
+}
+
+impl<'a, T: Hash + Eq, H: Hasher> Iterator<&'a T> for SymmetricDifference<'a, T, H> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        match self.a.next() {
+            Some(v) => Some(v),
+            None => self.b.next()
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, a) = self.a.size_hint();
+        let (_, b) = self.b.size_hint();
+        (0, Some(a.get() + b.get()))
+    }
+}
+
+/// An iterator over the values present in either of two sets
+pub struct Union<'a, T, H = State> {
+    priv a: SetItems<'a, T>,
+    priv b: Difference<'a, T, H>
+}
+
+impl<'a, T: Hash + Eq, H: Hasher> Iterator<&'a T> for Union<'a, T, H> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        match self.a.next() {
+            Some(v) => Some(v),
+            None => self.b.next()
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (a, _) = self.a.size_hint();
+        let (_, b) = self.b.size_hint();
+        (a, Some(a + b.get()))
+    }
+}
@@ -12,7 +12,7 @@
 #[no_std];
 #[allow(ctypes)];
 #[crate_type = "rlib"];
-#[feature(macro_rules)];
+#[feature(macro_rules, default_type_params, thread_local)];
 
 #[cfg(libc)]
 pub mod arc;
@@ -30,6 +30,7 @@ pub mod cmp;
 #[cfg(libc)]
 pub mod concurrent;
 pub mod container;
+pub mod entropy;
 pub mod fail;
 #[cfg(libc)]
 pub mod hash;
@@ -38,6 +39,8 @@ pub mod io;
 pub mod iter;
 pub mod kinds;
 #[cfg(libc)]
+pub mod linked_hash_map;
+#[cfg(libc)]
 pub mod lru;
 pub mod mem;
 pub mod ops;
@@ -52,6 +55,8 @@ pub mod thread;
 #[cfg(libc)]
 pub mod time;
 #[cfg(libc)]
+pub mod tree;
+#[cfg(libc)]
 pub mod vec;
 
 pub mod uint;
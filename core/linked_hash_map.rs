@@ -0,0 +1,522 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A hash map threaded with a doubly-linked list, remembering the order of its entries
+//!
+//! By default entries are kept in insertion order: replacing the value of an existing key leaves
+//! its position unchanged. In access order, looking up or replacing an entry through `get`,
+//! `get_mut` or `swap` also moves it to the back, so the front holds the least-recently-used
+//! entry.
+//!
+//! # Example
+//!
+//! ```rust
+//! let mut map = LinkedHashMap::new();
+//! map.insert(1, 10);
+//! map.insert(2, 20);
+//! map.insert(3, 30);
+//! map.move_to_front(&3);
+//! assert_eq!(map.pop_front(), Some((3, 30)));
+//! assert_eq!(map.pop_back(), Some((2, 20)));
+//! ```
+
+use container::Container;
+use hash::{HashMap, HashBytes, Hasher, State, random_keys};
+use iter::{Iterator, DoubleEndedIterator};
+use kinds::marker;
+use mem::{replace, transmute};
+use option::{Some, None, Option};
+use cmp::{Eq, Equiv};
+use ops::Drop;
+
+struct KeyRef<K> { k: *K }
+
+struct LinkedEntry<K, V> {
+    key: Option<K>,
+    value: Option<V>,
+    next: *mut LinkedEntry<K, V>,
+    prev: *mut LinkedEntry<K, V>,
+}
+
+/// A hash map preserving the insertion order, or optionally the access order, of its entries
+pub struct LinkedHashMap<K, V, H = State> {
+    priv map: HashMap<KeyRef<K>, ~LinkedEntry<K, V>, H>,
+    priv head: *mut LinkedEntry<K, V>,
+    priv tail: *mut LinkedEntry<K, V>,
+    priv access_order: bool
+}
+
+impl<K: HashBytes> HashBytes for KeyRef<K> {
+    fn hash_bytes(&self, f: |&[u8]|) {
+        unsafe { (*self.k).hash_bytes(f) }
+    }
+}
+
+impl<K: Eq> Eq for KeyRef<K> {
+    fn eq(&self, other: &KeyRef<K>) -> bool {
+        unsafe { (*self.k).eq(&*other.k) }
+    }
+}
+
+// A borrowed form of a key, for looking up `KeyRef` entries by equivalence
+struct QueryRef<'a, Q> { q: &'a Q }
+
+impl<'a, Q: HashBytes> HashBytes for QueryRef<'a, Q> {
+    fn hash_bytes(&self, f: |&[u8]|) {
+        self.q.hash_bytes(f)
+    }
+}
+
+impl<'a, K, Q: Equiv<K>> Equiv<KeyRef<K>> for QueryRef<'a, Q> {
+    fn equiv(&self, other: &KeyRef<K>) -> bool {
+        unsafe { self.q.equiv(&*other.k) }
+    }
+}
+
+impl<K, V> LinkedEntry<K, V> {
+    fn new() -> LinkedEntry<K, V> {
+        LinkedEntry {
+            key: None,
+            value: None,
+            next: 0 as *mut LinkedEntry<K, V>,
+            prev: 0 as *mut LinkedEntry<K, V>
+        }
+    }
+
+    fn with_key_value(k: K, v: V) -> LinkedEntry<K, V> {
+        LinkedEntry {
+            key: Some(k),
+            value: Some(v),
+            next: 0 as *mut LinkedEntry<K, V>,
+            prev: 0 as *mut LinkedEntry<K, V>
+        }
+    }
+}
+
+impl<K: HashBytes + Eq, V> LinkedHashMap<K, V> {
+    /// Create an empty `LinkedHashMap` using SipHash-2-4 with a random key.
+    #[inline]
+    pub fn new() -> LinkedHashMap<K, V> {
+        LinkedHashMap::with_capacity(0)
+    }
+
+    /// Create an empty `LinkedHashMap` with space for at least `capacity` elements, using
+    /// SipHash-2-4 with a random key.
+    pub fn with_capacity(capacity: uint) -> LinkedHashMap<K, V> {
+        let (k0, k1) = random_keys();
+        LinkedHashMap::with_capacity_and_keys(k0, k1, capacity)
+    }
+
+    /// Create an empty `LinkedHashMap` with space for at least `capacity` elements, using
+    /// SipHash-2-4 with the specified 128-bit key (`k0` and `k1`).
+    pub fn with_capacity_and_keys(k0: u64, k1: u64, capacity: uint) -> LinkedHashMap<K, V> {
+        LinkedHashMap::with_capacity_and_hasher(State::new(k0, k1), capacity)
+    }
+}
+
+impl<K: HashBytes + Eq, V, H: Hasher> LinkedHashMap<K, V, H> {
+    /// Create an empty `LinkedHashMap` using `hasher` as the hash function, with space for at
+    /// least `capacity` elements.
+    pub fn with_capacity_and_hasher(hasher: H, capacity: uint) -> LinkedHashMap<K, V, H> {
+        let map = LinkedHashMap {
+            map: HashMap::with_capacity_and_hasher(hasher, capacity),
+            head: unsafe { transmute(~LinkedEntry::<K, V>::new()) },
+            tail: unsafe { transmute(~LinkedEntry::<K, V>::new()) },
+            access_order: false
+        };
+        unsafe {
+            (*map.head).next = map.tail;
+            (*map.tail).prev = map.head;
+        }
+        map
+    }
+
+    /// Choose between keeping the entries in insertion order, and moving an entry to the back
+    /// whenever it is looked up with `get` or `get_mut` or replaced with `swap`.
+    #[inline]
+    pub fn set_access_order(&mut self, access_order: bool) {
+        self.access_order = access_order;
+    }
+
+    /// Insert a key-value pair into the map. An existing value for a key is replaced by the new
+    /// value. Return true if the key did not already exist in the map.
+    #[inline]
+    pub fn insert(&mut self, k: K, v: V) -> bool {
+        self.swap(k, v).is_none()
+    }
+
+    /// Insert a key-value pair into the map, at the back. An existing value for a key is replaced
+    /// by the new value, and only moves to the back in access order. Return the old value if the
+    /// key already existed in the map.
+    pub fn swap(&mut self, k: K, v: V) -> Option<V> {
+        let (node_ptr, node_opt, old) = match self.map.find_mut(&KeyRef{k: &k}) {
+            Some(node) => {
+                let old = replace(&mut node.value, Some(v));
+                let node_ptr: *mut LinkedEntry<K, V> = &mut **node;
+                (node_ptr, None, old)
+            }
+            None => {
+                let mut node = ~LinkedEntry::with_key_value(k, v);
+                let node_ptr: *mut LinkedEntry<K, V> = &mut *node;
+                (node_ptr, Some(node), None)
+            }
+        };
+        match node_opt {
+            None => {
+                if self.access_order {
+                    self.detach(node_ptr);
+                    self.attach_back(node_ptr);
+                }
+            }
+            Some(node) => {
+                let keyref = unsafe { (*node_ptr).key.as_ref().get() };
+                self.map.swap(KeyRef{k: keyref}, node);
+                self.attach_back(node_ptr);
+            }
+        }
+        old
+    }
+
+    /// Return a reference to the value corresponding to the key, without changing its position
+    pub fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
+        match self.map.find(&KeyRef{k: k}) {
+            None => None,
+            Some(node) => node.value.as_ref()
+        }
+    }
+
+    /// Return a reference to the value corresponding to a key equivalent to `k`, without changing
+    /// its position
+    pub fn find_equiv<'a, Q: HashBytes + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V> {
+        match self.map.find_equiv(&QueryRef{q: k}) {
+            None => None,
+            Some(node) => node.value.as_ref()
+        }
+    }
+
+    /// Return true if the map contains a value for the specified key
+    #[inline]
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.map.contains_key(&KeyRef{k: k})
+    }
+
+    /// Return a reference to the value corresponding to the key, moving it to the back in access
+    /// order
+    pub fn get<'a>(&'a mut self, k: &K) -> Option<&'a V> {
+        match self.find_node(k) {
+            None => None,
+            Some(node_ptr) => {
+                self.touch(node_ptr);
+                unsafe { (*node_ptr).value.as_ref() }
+            }
+        }
+    }
+
+    /// Return a reference to the value corresponding to a key equivalent to `k`, moving it to the
+    /// back in access order
+    pub fn get_equiv<'a, Q: HashBytes + Equiv<K>>(&'a mut self, k: &Q) -> Option<&'a V> {
+        let node_ptr: *mut LinkedEntry<K, V> = match self.map.find_mut_equiv(&QueryRef{q: k}) {
+            None => return None,
+            Some(node) => &mut **node
+        };
+        self.touch(node_ptr);
+        unsafe { (*node_ptr).value.as_ref() }
+    }
+
+    /// Return a mutable reference to the value corresponding to the key, moving it to the back in
+    /// access order
+    pub fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+        match self.find_node(k) {
+            None => None,
+            Some(node_ptr) => {
+                self.touch(node_ptr);
+                unsafe { (*node_ptr).value.as_mut() }
+            }
+        }
+    }
+
+    /// Remove a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn pop(&mut self, k: &K) -> Option<V> {
+        match self.find_node(k) {
+            None => None,
+            Some(node_ptr) => {
+                let (_, v) = self.pop_node(node_ptr);
+                Some(v)
+            }
+        }
+    }
+
+    /// Remove and return the entry at the front of the map
+    pub fn pop_front(&mut self) -> Option<(K, V)> {
+        if self.len() == 0 {
+            None
+        } else {
+            let node_ptr = unsafe { (*self.head).next };
+            Some(self.pop_node(node_ptr))
+        }
+    }
+
+    /// Remove and return the entry at the back of the map
+    pub fn pop_back(&mut self) -> Option<(K, V)> {
+        if self.len() == 0 {
+            None
+        } else {
+            let node_ptr = unsafe { (*self.tail).prev };
+            Some(self.pop_node(node_ptr))
+        }
+    }
+
+    /// Move the entry for the key to the front of the map. Return false if the key is not in the
+    /// map.
+    pub fn move_to_front(&mut self, k: &K) -> bool {
+        match self.find_node(k) {
+            None => false,
+            Some(node_ptr) => {
+                self.detach(node_ptr);
+                self.attach_front(node_ptr);
+                true
+            }
+        }
+    }
+
+    /// Move the entry for the key to the back of the map. Return false if the key is not in the
+    /// map.
+    pub fn move_to_back(&mut self, k: &K) -> bool {
+        match self.find_node(k) {
+            None => false,
+            Some(node_ptr) => {
+                self.detach(node_ptr);
+                self.attach_back(node_ptr);
+                true
+            }
+        }
+    }
+
+    /// Remove all entries from the map
+    pub fn clear(&mut self) {
+        self.map.clear();
+        unsafe {
+            (*self.head).next = self.tail;
+            (*self.tail).prev = self.head;
+        }
+    }
+
+    #[inline]
+    fn find_node(&mut self, k: &K) -> Option<*mut LinkedEntry<K, V>> {
+        match self.map.find_mut(&KeyRef{k: k}) {
+            None => None,
+            Some(node) => {
+                let node_ptr: *mut LinkedEntry<K, V> = &mut **node;
+                Some(node_ptr)
+            }
+        }
+    }
+
+    // In access order, move a node that was just used to the back
+    #[inline]
+    fn touch(&mut self, node_ptr: *mut LinkedEntry<K, V>) {
+        if self.access_order {
+            self.detach(node_ptr);
+            self.attach_back(node_ptr);
+        }
+    }
+
+    fn pop_node(&mut self, node_ptr: *mut LinkedEntry<K, V>) -> (K, V) {
+        let node = unsafe {
+            let keyref = (*node_ptr).key.as_ref().get();
+            self.map.pop(&KeyRef{k: keyref}).get()
+        };
+        self.detach(node_ptr);
+        let ~LinkedEntry { key: key, value: value, .. } = node;
+        (key.get(), value.get())
+    }
+
+    #[inline]
+    fn detach(&mut self, node: *mut LinkedEntry<K, V>) {
+        unsafe {
+            (*(*node).prev).next = (*node).next;
+            (*(*node).next).prev = (*node).prev;
+        }
+    }
+
+    #[inline]
+    fn attach_front(&mut self, node: *mut LinkedEntry<K, V>) {
+        unsafe {
+            (*node).next = (*self.head).next;
+            (*node).prev = self.head;
+            (*self.head).next = node;
+            (*(*node).next).prev = node;
+        }
+    }
+
+    #[inline]
+    fn attach_back(&mut self, node: *mut LinkedEntry<K, V>) {
+        unsafe {
+            (*node).prev = (*self.tail).prev;
+            (*node).next = self.tail;
+            (*self.tail).prev = node;
+            (*(*node).prev).next = node;
+        }
+    }
+}
+
+impl<K: HashBytes + Eq, V, H: Hasher> Container for LinkedHashMap<K, V, H> {
+    /// Return the number of key-value pairs in the map.
+    fn len(&self) -> uint {
+        self.map.len()
+    }
+}
+
+impl<K: HashBytes + Eq, V, H: Hasher> LinkedHashMap<K, V, H> {
+    /// Return the entry at the front of the map
+    pub fn front<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        unsafe {
+            let node = (*self.head).next;
+            if node == self.tail {
+                None
+            } else {
+                Some(((*node).key.as_ref().get(), (*node).value.as_ref().get()))
+            }
+        }
+    }
+
+    /// Return the entry at the back of the map
+    pub fn back<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        unsafe {
+            let node = (*self.tail).prev;
+            if node == self.head {
+                None
+            } else {
+                Some(((*node).key.as_ref().get(), (*node).value.as_ref().get()))
+            }
+        }
+    }
+
+    /// An iterator visiting all key-value pairs from front to back
+    pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+        Entries {
+            head: self.head as *LinkedEntry<K, V>,
+            tail: self.tail as *LinkedEntry<K, V>,
+            remaining: self.len(),
+            lifetime: marker::ContravariantLifetime
+        }
+    }
+
+    /// An iterator visiting all keys from front to back
+    pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
+        Keys { iter: self.iter() }
+    }
+
+    /// An iterator visiting all values from front to back
+    pub fn values<'a>(&'a self) -> Values<'a, K, V> {
+        Values { iter: self.iter() }
+    }
+}
+
+#[unsafe_destructor]
+impl<K, V, H> Drop for LinkedHashMap<K, V, H> {
+    fn drop(&mut self) {
+        unsafe {
+            let _: ~LinkedEntry<K, V> = transmute(self.head);
+            let _: ~LinkedEntry<K, V> = transmute(self.tail);
+        }
+    }
+}
+
+/// An iterator over the key-value pairs of a `LinkedHashMap`, from front to back or from back to
+/// front
+pub struct Entries<'a, K, V> {
+    priv head: *LinkedEntry<K, V>,
+    priv tail: *LinkedEntry<K, V>,
+    priv remaining: uint,
+    priv lifetime: marker::ContravariantLifetime<'a>
+}
+
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None
+        }
+        self.remaining -= 1;
+        unsafe {
+            self.head = (*self.head).next as *LinkedEntry<K, V>;
+            Some(((*self.head).key.as_ref().get(), (*self.head).value.as_ref().get()))
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None
+        }
+        self.remaining -= 1;
+        unsafe {
+            self.tail = (*self.tail).prev as *LinkedEntry<K, V>;
+            Some(((*self.tail).key.as_ref().get(), (*self.tail).value.as_ref().get()))
+        }
+    }
+}
+
+/// An iterator over the keys of a `LinkedHashMap`
+pub struct Keys<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a K> for Keys<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<&'a K> for Keys<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a K> {
+        self.iter.next_back().map(|(k, _)| k)
+    }
+}
+
+/// An iterator over the values of a `LinkedHashMap`
+pub struct Values<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a V> for Values<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a V> {
+        self.iter.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<&'a V> for Values<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.iter.next_back().map(|(_, v)| v)
+    }
+}
@@ -15,7 +15,7 @@
 //! # Example
 //!
 //! ```rust
-//! let mut cache: LruCache<int, int> = LruCache::new(2);
+//! let mut cache: LruCache<int, int> = LruCache::with_capacity(2);
 //! cache.put(1, 10);
 //! cache.put(2, 20);
 //! cache.put(3, 30);
@@ -34,136 +34,68 @@
 //! ```
 
 use container::Container;
-use hash::{HashMap, HashBytes};
-use mem::transmute;
-use option::{Some, None, Option};
-use cmp::Eq;
-use ops::Drop;
-
-struct KeyRef<K> { k: *K }
-
-struct LruEntry<K, V> {
-    key: Option<K>,
-    value: Option<V>,
-    next: *mut LruEntry<K, V>,
-    prev: *mut LruEntry<K, V>,
-}
+use hash::{HashBytes, Hasher, State, random_keys};
+use linked_hash_map::LinkedHashMap;
+use option::{Option, None};
+use cmp::{Eq, Equiv};
 
 /// An LRU Cache.
-pub struct LruCache<K, V> {
-    priv map: HashMap<KeyRef<K>, ~LruEntry<K, V>>,
+///
+/// The entries are kept in a `LinkedHashMap` in access order, so the least-recently-used entry is
+/// always at the front.
+pub struct LruCache<K, V, H = State> {
+    priv map: LinkedHashMap<K, V, H>,
     priv max_size: uint,
-    priv head: *mut LruEntry<K, V>,
-    priv tail: *mut LruEntry<K, V>,
-}
-
-impl<K: HashBytes> HashBytes for KeyRef<K> {
-    fn hash_bytes(&self, f: |&[u8]|) {
-        unsafe { (*self.k).hash_bytes(f) }
-    }
 }
 
-impl<K: Eq> Eq for KeyRef<K> {
-    fn eq(&self, other: &KeyRef<K>) -> bool {
-        unsafe { (*self.k).eq(&*other.k) }
-    }
-}
-
-impl<K, V> LruEntry<K, V> {
-    fn new() -> LruEntry<K, V> {
-        LruEntry {
-            key: None,
-            value: None,
-            next: 0 as *mut LruEntry<K, V>,
-            prev: 0 as *mut LruEntry<K, V>
-        }
+impl<K: HashBytes + Eq, V> LruCache<K, V> {
+    /// Create an LRU Cache holding at most `capacity` items, using SipHash-2-4 with the specified
+    /// 128-bit key (`k0` and `k1`).
+    pub fn new(k0: u64, k1: u64, capacity: uint) -> LruCache<K, V> {
+        LruCache::with_hasher(State::new(k0, k1), capacity)
     }
 
-    fn with_key_value(k: K, v: V) -> LruEntry<K, V> {
-        LruEntry {
-            key: Some(k),
-            value: Some(v),
-            next: 0 as *mut LruEntry<K, V>,
-            prev: 0 as *mut LruEntry<K, V>
-        }
+    /// Create an LRU Cache holding at most `capacity` items, using SipHash-2-4 with a random key.
+    pub fn with_capacity(capacity: uint) -> LruCache<K, V> {
+        let (k0, k1) = random_keys();
+        LruCache::new(k0, k1, capacity)
     }
 }
 
-impl<K: HashBytes + Eq, V> LruCache<K, V> {
-    /// Create an LRU Cache holding at most `capacity` items.
-    pub fn new(k0: u64, k1: u64, capacity: uint) -> LruCache<K, V> {
-        let cache = LruCache {
-            map: HashMap::with_capacity_and_keys(k0, k1, capacity),
-            max_size: capacity,
-            head: unsafe { transmute(~LruEntry::<K, V>::new()) },
-            tail: unsafe { transmute(~LruEntry::<K, V>::new()) },
-        };
-        unsafe {
-            (*cache.head).next = cache.tail;
-            (*cache.tail).prev = cache.head;
-        }
-        return cache;
+impl<K: HashBytes + Eq, V, H: Hasher> LruCache<K, V, H> {
+    /// Create an LRU Cache holding at most `capacity` items, using `hasher` as the hash function.
+    pub fn with_hasher(hasher: H, capacity: uint) -> LruCache<K, V, H> {
+        let mut map = LinkedHashMap::with_capacity_and_hasher(hasher, capacity);
+        map.set_access_order(true);
+        LruCache { map: map, max_size: capacity }
     }
 
-    /// Put a key-value pair into cache.
-    pub fn put(&mut self, k: K, v: V) {
-        let mut key_existed = false;
-        let (node_ptr, node_opt) = match self.map.find_mut(&KeyRef{k: &k}) {
-            Some(node) => {
-                key_existed = true;
-                node.value = Some(v);
-                let node_ptr: *mut LruEntry<K, V> = &mut **node;
-                (node_ptr, None)
-            }
-            None => {
-                let mut node = ~LruEntry::with_key_value(k, v);
-                let node_ptr: *mut LruEntry<K, V> = &mut *node;
-                (node_ptr, Some(node))
-            }
-        };
-        if key_existed {
-            self.detach(node_ptr);
-            self.attach(node_ptr);
+    /// Put a key-value pair into cache. Return the least-recently-used pair if it was removed to
+    /// make room for the new one.
+    pub fn put(&mut self, k: K, v: V) -> Option<(K, V)> {
+        if self.map.insert(k, v) && self.len() > self.capacity() {
+            self.map.pop_front()
         } else {
-            let keyref = unsafe { (*node_ptr).key.as_ref().get() };
-            self.map.swap(KeyRef{k: keyref}, node_opt.get());
-            self.attach(node_ptr);
-            if self.len() > self.capacity() {
-                self.remove_lru();
-            }
+            None
         }
     }
 
     /// Return a value corresponding to the key in the cache.
+    #[inline]
     pub fn get<'a>(&'a mut self, k: &K) -> Option<&'a V> {
-        let (value, node_ptr_opt) = match self.map.find_mut(&KeyRef{k: k}) {
-            None => (None, None),
-            Some(node) => {
-                let node_ptr: *mut LruEntry<K, V> = &mut **node;
-                unsafe {
-                    match (*node_ptr).value {
-                        None => (None, None),
-                        Some(ref value) => (Some(value), Some(node_ptr))
-                    }
-                }
-            }
-        };
-        match node_ptr_opt {
-            None => (),
-            Some(node_ptr) => {
-                self.detach(node_ptr);
-                self.attach(node_ptr);
-            }
-        }
-        return value;
+        self.map.get(k)
+    }
+
+    /// Return a value corresponding to a key equivalent to `k` in the cache.
+    #[inline]
+    pub fn get_equiv<'a, Q: HashBytes + Equiv<K>>(&'a mut self, k: &Q) -> Option<&'a V> {
+        self.map.get_equiv(k)
     }
 
     /// Remove and return a value corresponding to the key from the cache.
+    #[inline]
     pub fn pop(&mut self, k: &K) -> Option<V> {
-        match self.map.pop(&KeyRef{k: k}) {
-            None => None,
-            Some(lru_entry) => lru_entry.value
-        }
+        self.map.pop(k)
     }
 
     /// Return the maximum number of key-value pairs the cache can hold.
@@ -174,60 +106,16 @@ impl<K: HashBytes + Eq, V> LruCache<K, V> {
     /// Change the number of key-value pairs the cache can hold. Remove
     /// least-recently-used key-value pairs if necessary.
     pub fn change_capacity(&mut self, capacity: uint) {
-        let mut i = capacity;
-        while i < self.len() {
-            self.remove_lru();
-            i += 1;
+        while self.len() > capacity {
+            self.map.pop_front();
         }
         self.max_size = capacity;
     }
-
-    #[inline]
-    fn remove_lru(&mut self) {
-        if self.len() > 0 {
-            let lru = unsafe { (*self.tail).prev };
-            self.detach(lru);
-            unsafe {
-                match (*lru).key {
-                    None => (),
-                    Some(ref k) => { self.map.pop(&KeyRef{k: k}); }
-                }
-            }
-        }
-    }
-
-    #[inline]
-    fn detach(&mut self, node: *mut LruEntry<K, V>) {
-        unsafe {
-            (*(*node).prev).next = (*node).next;
-            (*(*node).next).prev = (*node).prev;
-        }
-    }
-
-    #[inline]
-    fn attach(&mut self, node: *mut LruEntry<K, V>) {
-        unsafe {
-            (*node).next = (*self.head).next;
-            (*node).prev = self.head;
-            (*self.head).next = node;
-            (*(*node).next).prev = node;
-        }
-    }
 }
 
-impl<K: HashBytes + Eq, V> Container for LruCache<K, V> {
+impl<K: HashBytes + Eq, V, H: Hasher> Container for LruCache<K, V, H> {
     /// Return the number of key-value pairs in the cache.
     fn len(&self) -> uint {
         self.map.len()
     }
 }
-
-#[unsafe_destructor]
-impl<K, V> Drop for LruCache<K, V> {
-    fn drop(&mut self) {
-        unsafe {
-            let _: ~LruEntry<K, V> = transmute(self.head);
-            let _: ~LruEntry<K, V> = transmute(self.tail);
-        }
-    }
-}
@@ -11,16 +11,24 @@
 use container::Container;
 use c_types::{c_int, pthread_t, pthread_attr_t, pthread_mutex_t, pthread_mutexattr_t};
 use c_types::{pthread_cond_t, pthread_condattr_t, clockid_t, timespec};
+use c_types::{pthread_rwlock_t, pthread_rwlockattr_t};
 use time::Time;
 use fail::{EBUSY, ETIMEDOUT, abort, assert};
 use ops::Drop;
 use kinds::Send;
 use mem::{forget, uninit, transmute};
-use concurrent::Queue;
+use concurrent::{Queue, Worker, Stealer, Future, work_deque, promise};
+use deque::Deque;
+use arc::Arc;
+use atomic::{atomic_load_relaxed, atomic_xadd, atomic_xsub, atomic_fence};
+use kinds::marker::NoFreeze;
 use vec::Vec;
 use option::{Option, Some, None};
 use clone::Clone;
 
+#[path = "../macros.rs"]
+mod macros;
+
 #[deriving(Eq, Clone)]
 pub enum TimeoutStatus {
     NoTimeout,
@@ -52,6 +60,12 @@ extern {
     fn pthread_mutex_trylock(mutex: *mut pthread_mutex_t) -> c_int;
     fn pthread_mutex_unlock(mutex: *mut pthread_mutex_t) -> c_int;
 
+    fn pthread_rwlock_init(rwlock: *mut pthread_rwlock_t, attr: *pthread_rwlockattr_t) -> c_int;
+    fn pthread_rwlock_destroy(rwlock: *mut pthread_rwlock_t) -> c_int;
+    fn pthread_rwlock_rdlock(rwlock: *mut pthread_rwlock_t) -> c_int;
+    fn pthread_rwlock_wrlock(rwlock: *mut pthread_rwlock_t) -> c_int;
+    fn pthread_rwlock_unlock(rwlock: *mut pthread_rwlock_t) -> c_int;
+
     fn pthread_condattr_init(attr: *mut pthread_condattr_t) -> c_int;
     fn pthread_condattr_destroy(attr: *mut pthread_condattr_t) -> c_int;
     fn pthread_condattr_setclock(attr: *mut pthread_condattr_t, clock_id: clockid_t) -> c_int;
@@ -225,6 +239,74 @@ impl Drop for Mutex {
     }
 }
 
+/// A reader-writer lock, allowing either many readers or a single writer
+pub struct RwLock {
+    priv rwlock: pthread_rwlock_t
+}
+
+impl RwLock {
+    pub fn new() -> RwLock {
+        unsafe {
+            let mut rwlock = uninit();
+            if pthread_rwlock_init(&mut rwlock, 0 as *pthread_rwlockattr_t) != 0 {
+                abort()
+            }
+            RwLock { rwlock: rwlock }
+        }
+    }
+
+    /// Grab shared ownership of the lock, blocking while a writer owns it.
+    pub unsafe fn read(&mut self) {
+        assert(pthread_rwlock_rdlock(&mut self.rwlock) == 0)
+    }
+
+    /// Grab exclusive ownership of the lock, blocking while any reader or writer owns it.
+    pub unsafe fn write(&mut self) {
+        assert(pthread_rwlock_wrlock(&mut self.rwlock) == 0)
+    }
+
+    /// Grab shared ownership of the lock, returning a `RwLockGuard` value releasing ownership of
+    /// the lock in the destructor.
+    pub unsafe fn read_guard<'a>(&'a mut self) -> RwLockGuard<'a> {
+        self.read();
+        RwLockGuard { rwlock: self }
+    }
+
+    /// Grab exclusive ownership of the lock, returning a `RwLockGuard` value releasing ownership
+    /// of the lock in the destructor.
+    pub unsafe fn write_guard<'a>(&'a mut self) -> RwLockGuard<'a> {
+        self.write();
+        RwLockGuard { rwlock: self }
+    }
+
+    /// Release shared or exclusive ownership of the lock.
+    pub unsafe fn unlock(&mut self) {
+        assert(pthread_rwlock_unlock(&mut self.rwlock) == 0)
+    }
+}
+
+impl Drop for RwLock {
+    fn drop(&mut self) {
+        unsafe {
+            assert(pthread_rwlock_destroy(&mut self.rwlock) == 0)
+        }
+    }
+}
+
+/// A scoped ownership of a `RwLock`, released by the destructor
+pub struct RwLockGuard<'a> {
+    priv rwlock: &'a mut RwLock
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for RwLockGuard<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            self.rwlock.unlock()
+        }
+    }
+}
+
 pub struct Cond {
     priv cond: pthread_cond_t
 }
@@ -345,6 +427,13 @@ impl Pool {
     pub fn submit(&self, task: proc()) {
         self.queue.push(Some(task))
     }
+
+    /// Submit a task to the thread pool, returning a future for its result.
+    pub fn execute<A: Send>(&self, task: proc() -> A) -> Future<A> {
+        let (promise, future) = promise();
+        self.submit(proc() promise.set(task()));
+        future
+    }
 }
 
 impl Drop for Pool {
@@ -356,3 +445,220 @@ impl Drop for Pool {
         }
     }
 }
+
+thread_local!(current_worker, *mut u8, 0 as *mut u8)
+
+// The state of a thread running the workers of a `WorkStealingPool`, reachable from tasks through
+// the `current_worker` thread-local pointer
+struct WorkerContext {
+    pool: *mut StealPoolBox,
+    deque: *mut Worker<proc()>
+}
+
+struct StealPoolBox {
+    stealers: Vec<Stealer<proc()>>,
+    injector: Deque<proc()>,
+    mutex: Mutex,
+    cond: Cond,
+    sleeping: uint,
+    shutdown: bool,
+    no_freeze: NoFreeze
+}
+
+// A xorshift generator, used to pick the victims of stealing
+fn next_random(state: &mut u32) -> u32 {
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    *state = x;
+    x
+}
+
+impl StealPoolBox {
+    fn find_work(&mut self, rng: &mut u32) -> Option<proc()> {
+        unsafe {
+            let _guard = self.mutex.lock_guard();
+            match self.injector.pop_front() {
+                Some(task) => return Some(task),
+                None => ()
+            }
+        }
+        let n = self.stealers.len();
+        let start = next_random(rng) as uint % n;
+        let mut i = 0;
+        while i < n {
+            match self.stealers.as_slice()[(start + i) % n].steal() {
+                Some(task) => return Some(task),
+                None => ()
+            }
+            i += 1;
+        }
+        None
+    }
+
+    // Called with the lock held
+    fn has_work(&self) -> bool {
+        if !self.injector.is_empty() {
+            return true
+        }
+        let mut i = 0;
+        while i < self.stealers.len() {
+            if !self.stealers.as_slice()[i].is_empty() {
+                return true
+            }
+            i += 1;
+        }
+        false
+    }
+
+    // Block until there may be work to find. Return `false` if the pool is shut down and there is
+    // no work left.
+    fn park(&mut self) -> bool {
+        unsafe {
+            let mutex: &mut Mutex = transmute(&mut self.mutex);
+            let mut guard = mutex.lock_guard();
+            atomic_xadd(&mut self.sleeping, 1);
+            atomic_fence();
+            let mut running = true;
+            if !self.has_work() {
+                if self.shutdown {
+                    running = false
+                } else {
+                    self.cond.wait_guard(&mut guard)
+                }
+            }
+            atomic_xsub(&mut self.sleeping, 1);
+            running
+        }
+    }
+
+    // Wake up a parked worker after pushing to a local deque. The fence pairs with the one in
+    // `park`: either the parked worker sees the new task, or this sees the parked worker.
+    fn wake(&mut self) {
+        unsafe {
+            atomic_fence();
+            if atomic_load_relaxed(&self.sleeping) != 0 {
+                let _guard = self.mutex.lock_guard();
+                self.cond.signal()
+            }
+        }
+    }
+
+    fn push_local(&mut self, task: proc()) -> Option<proc()> {
+        unsafe {
+            let context = current_worker::get() as *mut WorkerContext;
+            if context == 0 as *mut WorkerContext || (*context).pool != self as *mut StealPoolBox {
+                return Some(task)
+            }
+            (*(*context).deque).push(task);
+            self.wake();
+            None
+        }
+    }
+}
+
+fn run_worker(pool: Arc<StealPoolBox>, deque: Worker<proc()>, seed: u32) {
+    unsafe {
+        let ptr: &mut StealPoolBox = transmute(pool.borrow());
+        let mut deque = deque;
+        let mut context = WorkerContext { pool: ptr as *mut StealPoolBox, deque: &mut deque };
+        current_worker::set(&mut context as *mut WorkerContext as *mut u8);
+        let mut rng = seed;
+        loop {
+            let task = match deque.pop() {
+                Some(task) => Some(task),
+                None => ptr.find_work(&mut rng)
+            };
+            match task {
+                Some(task) => task(),
+                None => if !ptr.park() { break }
+            }
+        }
+        current_worker::set(0 as *mut u8);
+    }
+}
+
+/// A pool of worker threads scheduling tasks by work stealing
+///
+/// Every worker owns a deque of tasks. Tasks submitted from a worker of the pool are pushed to its
+/// own deque and run in LIFO order, while tasks submitted from other threads go through a shared
+/// queue. An idle worker first takes tasks from the shared queue, then steals the oldest task of
+/// another worker picked at random, and sleeps if there is no work left anywhere.
+pub struct WorkStealingPool {
+    priv ptr: Arc<StealPoolBox>,
+    priv pool: Vec<Thread<()>>
+}
+
+impl WorkStealingPool {
+    /// Create a work-stealing thread pool with `n_threads` threads.
+    pub fn new(n_threads: uint) -> WorkStealingPool {
+        let mut workers = Vec::with_capacity(n_threads);
+        let mut stealers = Vec::with_capacity(n_threads);
+        let mut i = 0;
+        while i < n_threads {
+            let (worker, stealer) = work_deque();
+            workers.push(worker);
+            stealers.push(stealer);
+            i += 1;
+        }
+        let b = StealPoolBox { stealers: stealers, injector: Deque::new(), mutex: Mutex::new(),
+                               cond: Cond::new(), sleeping: 0, shutdown: false,
+                               no_freeze: NoFreeze };
+        let ptr = unsafe { Arc::new_unchecked(b) };
+
+        let mut pool = Vec::with_capacity(n_threads);
+        loop {
+            match workers.pop() {
+                Some(deque) => {
+                    let shared = ptr.clone();
+                    let seed = (pool.len() as u32 + 1) * 2654435769;
+                    pool.push(spawn(proc() run_worker(shared, deque, seed)));
+                }
+                None => break
+            }
+        }
+        WorkStealingPool { ptr: ptr, pool: pool }
+    }
+
+    /// Submit a task to the thread pool. When called from one of its workers, the task is pushed
+    /// to the deque of the worker.
+    pub fn submit(&self, task: proc()) {
+        unsafe {
+            let ptr: &mut StealPoolBox = transmute(self.ptr.borrow());
+            match ptr.push_local(task) {
+                None => (),
+                Some(task) => {
+                    let _guard = ptr.mutex.lock_guard();
+                    ptr.injector.push_back(task);
+                    ptr.cond.signal()
+                }
+            }
+        }
+    }
+}
+
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        unsafe {
+            let ptr: &mut StealPoolBox = transmute(self.ptr.borrow());
+            let _guard = ptr.mutex.lock_guard();
+            ptr.shutdown = true;
+            ptr.cond.broadcast()
+        }
+    }
+}
+
+/// Submit a task to the `WorkStealingPool` running the current thread, pushing it to the deque of
+/// the worker. Return `Some(task)` if the current thread is not a worker of a pool.
+///
+/// This allows tasks to spawn subtasks without a reference to the pool.
+pub fn submit_local(task: proc()) -> Option<proc()> {
+    unsafe {
+        let context = current_worker::get() as *mut WorkerContext;
+        if context == 0 as *mut WorkerContext {
+            return Some(task)
+        }
+        (*(*context).pool).push_local(task)
+    }
+}
@@ -43,7 +43,8 @@ impl Eq for Time {
 
 impl Ord for Time {
     fn lt(&self, other: &Time) -> bool {
-        self.time.tv_sec < other.time.tv_sec || self.time.tv_nsec < other.time.tv_nsec
+        self.time.tv_sec < other.time.tv_sec ||
+            (self.time.tv_sec == other.time.tv_sec && self.time.tv_nsec < other.time.tv_nsec)
     }
 }
 
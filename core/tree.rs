@@ -0,0 +1,709 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Ordered maps and sets implemented with a B-tree
+//!
+//! Each node holds between `B - 1` and `2 * B - 1` sorted keys (the root may hold fewer), so the
+//! tree stays shallow and a lookup touches few allocations. Nodes are split on the way down
+//! during insertion, and refilled from a sibling or merged on the way down during removal, so
+//! neither operation has to walk back up the tree.
+
+use container::Container;
+use vec::Vec;
+use cmp::{Ord, Less, Equal, Greater};
+use iter::{Iterator, DoubleEndedIterator, Invert};
+use option::{Option, Some, None};
+use mem::replace;
+
+static B: uint = 6;
+static MIN_LEN: uint = B - 1;
+static CAPACITY: uint = 2 * B - 1;
+
+struct Node<K, V> {
+    keys: Vec<K>,
+    vals: Vec<V>,
+    edges: Vec<~Node<K, V>> // empty for leaves
+}
+
+enum Search {
+    /// The key is at the index
+    Found(uint),
+    /// The key is absent, and belongs in the subtree at the edge index
+    GoDown(uint)
+}
+
+// Move the elements of `xs` from `at` onwards to the end of `ys`
+fn split_off<T>(xs: &mut Vec<T>, at: uint, ys: &mut Vec<T>) {
+    while xs.len() > at {
+        ys.push(xs.remove(at).get());
+    }
+}
+
+// Move all the elements of `ys` to the end of `xs`
+fn append<T>(xs: &mut Vec<T>, ys: Vec<T>) {
+    for y in ys.move_iter() {
+        xs.push(y);
+    }
+}
+
+impl<K, V> Node<K, V> {
+    #[inline]
+    fn new() -> Node<K, V> {
+        Node { keys: Vec::new(), vals: Vec::new(), edges: Vec::new() }
+    }
+
+    #[inline(always)]
+    fn is_leaf(&self) -> bool {
+        self.edges.len() == 0
+    }
+
+    #[inline(always)]
+    fn child_len(&self, i: uint) -> uint {
+        self.edges.as_slice()[i].keys.len()
+    }
+
+    /// Split the full child at `i` in two, moving its median key up into this node
+    fn split_child(&mut self, i: uint) {
+        let (key, val, right) = {
+            let child = &mut self.edges.as_mut_slice()[i];
+            let mut right = ~Node::new();
+            split_off(&mut child.keys, B, &mut right.keys);
+            split_off(&mut child.vals, B, &mut right.vals);
+            split_off(&mut child.edges, B, &mut right.edges);
+            (child.keys.pop().get(), child.vals.pop().get(), right)
+        };
+        self.keys.insert(i, key);
+        self.vals.insert(i, val);
+        self.edges.insert(i + 1, right);
+    }
+
+    /// Merge the key at `i` and the child to its right into the child to its left
+    fn merge_children(&mut self, i: uint) {
+        let key = self.keys.remove(i).get();
+        let val = self.vals.remove(i).get();
+        let ~Node { keys: keys, vals: vals, edges: edges } = self.edges.remove(i + 1).get();
+        let left = &mut self.edges.as_mut_slice()[i];
+        left.keys.push(key);
+        left.vals.push(val);
+        append(&mut left.keys, keys);
+        append(&mut left.vals, vals);
+        append(&mut left.edges, edges);
+    }
+
+    /// Move the last key of the child at `i` up into this node, and the key at `i` down into the
+    /// front of the child to its right
+    fn rotate_right(&mut self, i: uint) {
+        let (key, val, edge) = {
+            let left = &mut self.edges.as_mut_slice()[i];
+            let edge = left.edges.pop();
+            (left.keys.pop().get(), left.vals.pop().get(), edge)
+        };
+        let key = replace(&mut self.keys.as_mut_slice()[i], key);
+        let val = replace(&mut self.vals.as_mut_slice()[i], val);
+        let right = &mut self.edges.as_mut_slice()[i + 1];
+        right.keys.insert(0, key);
+        right.vals.insert(0, val);
+        match edge {
+            Some(edge) => right.edges.insert(0, edge),
+            None => {}
+        }
+    }
+
+    /// Move the first key of the child at `i + 1` up into this node, and the key at `i` down into
+    /// the back of the child to its left
+    fn rotate_left(&mut self, i: uint) {
+        let (key, val, edge) = {
+            let right = &mut self.edges.as_mut_slice()[i + 1];
+            let edge = right.edges.remove(0);
+            (right.keys.remove(0).get(), right.vals.remove(0).get(), edge)
+        };
+        let key = replace(&mut self.keys.as_mut_slice()[i], key);
+        let val = replace(&mut self.vals.as_mut_slice()[i], val);
+        let left = &mut self.edges.as_mut_slice()[i];
+        left.keys.push(key);
+        left.vals.push(val);
+        match edge {
+            Some(edge) => left.edges.push(edge),
+            None => {}
+        }
+    }
+
+    /// Make sure the child at `i` has more than the minimum number of keys, so a key can be
+    /// removed from it. Return the index of the child now covering the same range, which moves
+    /// if it had to be merged into its left sibling.
+    fn fill_child(&mut self, i: uint) -> uint {
+        if self.child_len(i) > MIN_LEN {
+            i
+        } else if i > 0 && self.child_len(i - 1) > MIN_LEN {
+            self.rotate_right(i - 1);
+            i
+        } else if i + 1 < self.edges.len() && self.child_len(i + 1) > MIN_LEN {
+            self.rotate_left(i);
+            i
+        } else if i + 1 < self.edges.len() {
+            self.merge_children(i);
+            i
+        } else {
+            self.merge_children(i - 1);
+            i - 1
+        }
+    }
+
+    fn pop_min(&mut self) -> (K, V) {
+        if self.is_leaf() {
+            (self.keys.remove(0).get(), self.vals.remove(0).get())
+        } else {
+            let i = self.fill_child(0);
+            self.edges.as_mut_slice()[i].pop_min()
+        }
+    }
+
+    fn pop_max(&mut self) -> (K, V) {
+        if self.is_leaf() {
+            (self.keys.pop().get(), self.vals.pop().get())
+        } else {
+            let last = self.edges.len() - 1;
+            let i = self.fill_child(last);
+            self.edges.as_mut_slice()[i].pop_max()
+        }
+    }
+}
+
+impl<K: Ord, V> Node<K, V> {
+    fn search(&self, k: &K) -> Search {
+        let keys = self.keys.as_slice();
+        let mut i = 0;
+        while i < keys.len() {
+            match k.cmp(&keys[i]) {
+                Less => return GoDown(i),
+                Equal => return Found(i),
+                Greater => i += 1
+            }
+        }
+        GoDown(i)
+    }
+
+    fn find_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+        match self.search(k) {
+            Found(i) => Some(&mut self.vals.as_mut_slice()[i]),
+            GoDown(i) => {
+                if self.is_leaf() {
+                    None
+                } else {
+                    self.edges.as_mut_slice()[i].find_mut(k)
+                }
+            }
+        }
+    }
+
+    /// Insert into the subtree rooted at this node, which must not be full
+    fn insert(&mut self, k: K, v: V) -> Option<V> {
+        match self.search(&k) {
+            Found(i) => Some(replace(&mut self.vals.as_mut_slice()[i], v)),
+            GoDown(i) => {
+                if self.is_leaf() {
+                    self.keys.insert(i, k);
+                    self.vals.insert(i, v);
+                    return None
+                }
+
+                let mut i = i;
+                if self.child_len(i) == CAPACITY {
+                    self.split_child(i);
+                    let order = k.cmp(&self.keys.as_slice()[i]);
+                    match order {
+                        Less => {}
+                        Equal => return Some(replace(&mut self.vals.as_mut_slice()[i], v)),
+                        Greater => i += 1
+                    }
+                }
+                self.edges.as_mut_slice()[i].insert(k, v)
+            }
+        }
+    }
+
+    /// Remove from the subtree rooted at this node, which must have more than the minimum number
+    /// of keys unless it is the root
+    fn remove(&mut self, k: &K) -> Option<V> {
+        match self.search(k) {
+            Found(i) => {
+                if self.is_leaf() {
+                    self.keys.remove(i);
+                    self.vals.remove(i)
+                } else if self.child_len(i) > MIN_LEN {
+                    // Replace the key with its predecessor
+                    let (key, val) = self.edges.as_mut_slice()[i].pop_max();
+                    self.keys.as_mut_slice()[i] = key;
+                    Some(replace(&mut self.vals.as_mut_slice()[i], val))
+                } else if self.child_len(i + 1) > MIN_LEN {
+                    // Replace the key with its successor
+                    let (key, val) = self.edges.as_mut_slice()[i + 1].pop_min();
+                    self.keys.as_mut_slice()[i] = key;
+                    Some(replace(&mut self.vals.as_mut_slice()[i], val))
+                } else {
+                    self.merge_children(i);
+                    self.edges.as_mut_slice()[i].remove(k)
+                }
+            }
+            GoDown(i) => {
+                if self.is_leaf() {
+                    return None
+                }
+                let i = self.fill_child(i);
+                self.edges.as_mut_slice()[i].remove(k)
+            }
+        }
+    }
+}
+
+/// An ordered map implemented with a B-tree
+pub struct TreeMap<K, V> {
+    priv root: ~Node<K, V>,
+    priv length: uint
+}
+
+impl<K: Ord, V> Container for TreeMap<K, V> {
+    /// Return the number of elements in the map
+    #[inline(always)]
+    fn len(&self) -> uint { self.length }
+}
+
+impl<K: Ord, V> TreeMap<K, V> {
+    /// Create an empty `TreeMap`
+    #[inline]
+    pub fn new() -> TreeMap<K, V> {
+        TreeMap { root: ~Node::new(), length: 0 }
+    }
+
+    /// Return a reference to the value corresponding to the key
+    pub fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
+        let mut node: &'a Node<K, V> = &*self.root;
+        loop {
+            match node.search(k) {
+                Found(i) => return Some(&node.vals.as_slice()[i]),
+                GoDown(i) => {
+                    if node.is_leaf() {
+                        return None
+                    }
+                    node = &*node.edges.as_slice()[i];
+                }
+            }
+        }
+    }
+
+    /// Return a mutable reference to the value corresponding to the key
+    #[inline]
+    pub fn find_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+        self.root.find_mut(k)
+    }
+
+    /// Return true if the map contains a value for the specified key
+    #[inline]
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.find(k).is_some()
+    }
+
+    /// Insert a key-value pair into the map. An existing value for a key is replaced by the new
+    /// value. Return true if the key did not already exist in the map.
+    #[inline]
+    pub fn insert(&mut self, k: K, v: V) -> bool {
+        self.swap(k, v).is_none()
+    }
+
+    /// Insert a key-value pair into the map. An existing value for a key is replaced by the new
+    /// value. Return the old value if the key already existed in the map.
+    pub fn swap(&mut self, k: K, v: V) -> Option<V> {
+        if self.root.keys.len() == CAPACITY {
+            let old_root = replace(&mut self.root, ~Node::new());
+            self.root.edges.push(old_root);
+            self.root.split_child(0);
+        }
+        let old = self.root.insert(k, v);
+        if old.is_none() {
+            self.length += 1;
+        }
+        old
+    }
+
+    /// Remove a key from the map, returning the value at the key if the key was previously in the
+    /// map.
+    pub fn pop(&mut self, k: &K) -> Option<V> {
+        let old = self.root.remove(k);
+        if old.is_some() {
+            self.length -= 1;
+        }
+        if self.root.keys.len() == 0 && !self.root.is_leaf() {
+            let child = self.root.edges.pop().get();
+            self.root = child;
+        }
+        old
+    }
+
+    /// Remove all entries from the map
+    pub fn clear(&mut self) {
+        self.root = ~Node::new();
+        self.length = 0;
+    }
+
+    /// An iterator visiting the key-value pairs with keys not less than `k`, in order
+    pub fn lower_bound<'a>(&'a self, k: &K) -> Entries<'a, K, V> {
+        self.bound(|key| *key < *k)
+    }
+
+    /// An iterator visiting the key-value pairs with keys greater than `k`, in order
+    pub fn upper_bound<'a>(&'a self, k: &K) -> Entries<'a, K, V> {
+        self.bound(|key| *key <= *k)
+    }
+
+    // Position the front of an iterator at the first key for which `before` is false. The keys
+    // for which `before` is true must all precede the others.
+    fn bound<'a>(&'a self, before: |&K| -> bool) -> Entries<'a, K, V> {
+        let mut entries = Entries {
+            front: Vec::new(),
+            back: Vec::new(),
+            front_last: 0 as *K,
+            back_last: 0 as *K,
+            lower: 0,
+            upper: self.length
+        };
+        push_rightmost(&mut entries.back, &*self.root);
+
+        // The front stack is left pointing at the first key not before the bound, and the
+        // nearest key before the bound is recorded as already visited, so the back of the
+        // iterator stops there.
+        let mut node: &'a Node<K, V> = &*self.root;
+        loop {
+            let keys = node.keys.as_slice();
+            let mut i = 0;
+            while i < keys.len() && before(&keys[i]) {
+                i += 1;
+            }
+            entries.front.push((node, i));
+            if i > 0 {
+                entries.front_last = &keys[i - 1] as *K;
+            }
+            if node.is_leaf() {
+                break
+            }
+            node = &*node.edges.as_slice()[i];
+        }
+        entries
+    }
+}
+
+impl<K, V> TreeMap<K, V> {
+    /// Return the first key-value pair in the map, with the smallest key
+    pub fn first<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        let mut node: &'a Node<K, V> = &*self.root;
+        while !node.is_leaf() {
+            node = &*node.edges.as_slice()[0];
+        }
+        if node.keys.len() == 0 {
+            None
+        } else {
+            Some((&node.keys.as_slice()[0], &node.vals.as_slice()[0]))
+        }
+    }
+
+    /// Return the last key-value pair in the map, with the largest key
+    pub fn last<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        let mut node: &'a Node<K, V> = &*self.root;
+        while !node.is_leaf() {
+            node = &*node.edges.as_slice()[node.edges.len() - 1];
+        }
+        let len = node.keys.len();
+        if len == 0 {
+            None
+        } else {
+            Some((&node.keys.as_slice()[len - 1], &node.vals.as_slice()[len - 1]))
+        }
+    }
+
+    /// An iterator visiting all key-value pairs in order
+    pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+        let mut entries = Entries {
+            front: Vec::new(),
+            back: Vec::new(),
+            front_last: 0 as *K,
+            back_last: 0 as *K,
+            lower: self.length,
+            upper: self.length
+        };
+        push_leftmost(&mut entries.front, &*self.root);
+        push_rightmost(&mut entries.back, &*self.root);
+        entries
+    }
+
+    /// An iterator visiting all key-value pairs in reverse order
+    #[inline]
+    pub fn rev_iter<'a>(&'a self) -> RevEntries<'a, K, V> {
+        self.iter().invert()
+    }
+
+    /// An iterator visiting all keys in order
+    #[inline]
+    pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
+        Keys { iter: self.iter() }
+    }
+
+    /// An iterator visiting all values in order
+    #[inline]
+    pub fn values<'a>(&'a self) -> Values<'a, K, V> {
+        Values { iter: self.iter() }
+    }
+}
+
+// Push the path from `node` to the first key of its subtree onto a front stack
+fn push_leftmost<'a, K, V>(stack: &mut Vec<(&'a Node<K, V>, uint)>, node: &'a Node<K, V>) {
+    let mut node = node;
+    loop {
+        stack.push((node, 0));
+        if node.is_leaf() {
+            break
+        }
+        node = &*node.edges.as_slice()[0];
+    }
+}
+
+// Push the path from `node` to the last key of its subtree onto a back stack
+fn push_rightmost<'a, K, V>(stack: &mut Vec<(&'a Node<K, V>, uint)>, node: &'a Node<K, V>) {
+    let mut node = node;
+    loop {
+        let len = node.keys.len();
+        stack.push((node, len));
+        if node.is_leaf() {
+            break
+        }
+        node = &*node.edges.as_slice()[len];
+    }
+}
+
+/// An iterator over the key-value pairs of a `TreeMap`, in order from the front and in reverse
+/// order from the back
+///
+/// Each end keeps the path from the root to its position. The front stack holds the index of the
+/// next key to visit in each node, and the back stack holds the number of keys left to visit. The
+/// ends have met once one reaches the key last visited by the other.
+pub struct Entries<'a, K, V> {
+    priv front: Vec<(&'a Node<K, V>, uint)>,
+    priv back: Vec<(&'a Node<K, V>, uint)>,
+    priv front_last: *K,
+    priv back_last: *K,
+    priv lower: uint,
+    priv upper: uint
+}
+
+/// An iterator over the key-value pairs of a `TreeMap`, in reverse order
+pub type RevEntries<'a, K, V> = Invert<Entries<'a, K, V>>;
+
+impl<'a, K, V> Entries<'a, K, V> {
+    #[inline]
+    fn finish(&mut self) -> Option<(&'a K, &'a V)> {
+        self.front.truncate(0);
+        self.back.truncate(0);
+        self.lower = 0;
+        self.upper = 0;
+        None
+    }
+
+    #[inline]
+    fn consume(&mut self) {
+        if self.lower > 0 {
+            self.lower -= 1;
+        }
+        self.upper -= 1;
+    }
+}
+
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            let (node, i) = match self.front.pop() {
+                Some(top) => top,
+                None => return None
+            };
+            if i < node.keys.len() {
+                let key = &node.keys.as_slice()[i];
+                if key as *K == self.back_last {
+                    return self.finish()
+                }
+                self.front.push((node, i + 1));
+                if !node.is_leaf() {
+                    push_leftmost(&mut self.front, &*node.edges.as_slice()[i + 1]);
+                }
+                self.front_last = key as *K;
+                self.consume();
+                return Some((key, &node.vals.as_slice()[i]))
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.lower, Some(self.upper))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            let (node, i) = match self.back.pop() {
+                Some(top) => top,
+                None => return None
+            };
+            if i > 0 {
+                let key = &node.keys.as_slice()[i - 1];
+                if key as *K == self.front_last {
+                    return self.finish()
+                }
+                self.back.push((node, i - 1));
+                if !node.is_leaf() {
+                    push_rightmost(&mut self.back, &*node.edges.as_slice()[i - 1]);
+                }
+                self.back_last = key as *K;
+                self.consume();
+                return Some((key, &node.vals.as_slice()[i - 1]))
+            }
+        }
+    }
+}
+
+/// An iterator over the keys of a `TreeMap`
+pub struct Keys<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a K> for Keys<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<&'a K> for Keys<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a K> {
+        self.iter.next_back().map(|(k, _)| k)
+    }
+}
+
+/// An iterator over the values of a `TreeMap`
+pub struct Values<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a V> for Values<'a, K, V> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a V> {
+        self.iter.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<&'a V> for Values<'a, K, V> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.iter.next_back().map(|(_, v)| v)
+    }
+}
+
+/// An ordered set implemented as a `TreeMap` where the value is `()`
+pub struct TreeSet<T> {
+    priv map: TreeMap<T, ()>
+}
+
+/// An iterator over the elements of a `TreeSet`
+pub type SetItems<'a, T> = Keys<'a, T, ()>;
+
+/// An iterator over the elements of a `TreeSet`, in reverse order
+pub type RevSetItems<'a, T> = Invert<Keys<'a, T, ()>>;
+
+impl<T: Ord> Container for TreeSet<T> {
+    /// Return the number of elements in the set
+    #[inline(always)]
+    fn len(&self) -> uint { self.map.len() }
+}
+
+impl<T: Ord> TreeSet<T> {
+    /// Create an empty `TreeSet`
+    #[inline]
+    pub fn new() -> TreeSet<T> {
+        TreeSet { map: TreeMap::new() }
+    }
+
+    /// Add a value to the set. Return true if the value was not already present in the set.
+    #[inline]
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ())
+    }
+
+    /// Return true if the set contains a value
+    #[inline]
+    pub fn contains(&self, value: &T) -> bool {
+        self.map.contains_key(value)
+    }
+
+    /// Remove a value from the set. Return true if the value was present in the set.
+    #[inline]
+    pub fn remove(&mut self, value: &T) -> bool {
+        self.map.pop(value).is_some()
+    }
+
+    /// Remove all values from the set
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// An iterator visiting the values not less than `value`, in order
+    #[inline]
+    pub fn lower_bound<'a>(&'a self, value: &T) -> SetItems<'a, T> {
+        Keys { iter: self.map.lower_bound(value) }
+    }
+
+    /// An iterator visiting the values greater than `value`, in order
+    #[inline]
+    pub fn upper_bound<'a>(&'a self, value: &T) -> SetItems<'a, T> {
+        Keys { iter: self.map.upper_bound(value) }
+    }
+
+    /// Return the smallest value in the set
+    #[inline]
+    pub fn first<'a>(&'a self) -> Option<&'a T> {
+        self.map.first().map(|(k, _)| k)
+    }
+
+    /// Return the largest value in the set
+    #[inline]
+    pub fn last<'a>(&'a self) -> Option<&'a T> {
+        self.map.last().map(|(k, _)| k)
+    }
+
+    /// An iterator visiting all values in order
+    #[inline]
+    pub fn iter<'a>(&'a self) -> SetItems<'a, T> {
+        self.map.keys()
+    }
+
+    /// An iterator visiting all values in reverse order
+    #[inline]
+    pub fn rev_iter<'a>(&'a self) -> RevSetItems<'a, T> {
+        self.map.keys().invert()
+    }
+}
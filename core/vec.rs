@@ -10,15 +10,15 @@
 
 use container::Container;
 use mem::{forget, move_val_init, size_of, transmute};
-use fail::out_of_memory;
+use fail::{abort, out_of_memory};
 use heap::{free, alloc, realloc};
 use ops::Drop;
 use slice::{Items, Slice, iter, unchecked_get, unchecked_mut_get};
-use ptr::{offset, read_ptr};
+use ptr::{copy_memory, offset, read_ptr};
 use uint::mul_with_overflow;
 use option::{Option, Some, None};
 use iter::{Iterator, DoubleEndedIterator};
-use cmp::expect;
+use cmp::{Eq, Equiv, expect};
 use clone::Clone;
 
 #[path = "../macros.rs"]
@@ -113,6 +113,37 @@ impl<T> Vec<T> {
         }
     }
 
+    /// Insert `value` at position `index`, shifting the following elements to the right.
+    pub fn insert(&mut self, index: uint, value: T) {
+        let len = self.len;
+        if index > len {
+            abort()
+        }
+        self.push(value);
+        unsafe {
+            let p = offset(self.ptr as *T, index as int) as *mut T;
+            let tmp = read_ptr(offset(self.ptr as *T, len as int));
+            copy_memory(offset(p as *T, 1) as *mut T, p as *T, len - index);
+            move_val_init(&mut *p, tmp);
+        }
+    }
+
+    /// Remove and return the element at position `index`, shifting the following elements to the
+    /// left. Return `None` if `index` is out of bounds.
+    pub fn remove(&mut self, index: uint) -> Option<T> {
+        let len = self.len;
+        if index >= len {
+            return None
+        }
+        unsafe {
+            let p = offset(self.ptr as *T, index as int) as *mut T;
+            let value = read_ptr(p as *T);
+            copy_memory(p, offset(p as *T, 1), len - index - 1);
+            self.len = len - 1;
+            Some(value)
+        }
+    }
+
     pub fn truncate(&mut self, len: uint) {
         unsafe {
             let mut i = len;
@@ -194,6 +225,23 @@ impl<T> Drop for Vec<T> {
     }
 }
 
+impl<'a, T: Eq> Equiv<Vec<T>> for &'a [T] {
+    fn equiv(&self, other: &Vec<T>) -> bool {
+        let xs = other.as_slice();
+        if self.len() != xs.len() {
+            return false
+        }
+        let mut i = 0;
+        while i < xs.len() {
+            if self[i] != xs[i] {
+                return false
+            }
+            i += 1;
+        }
+        true
+    }
+}
+
 pub struct MoveItems<T> {
     priv allocation: *mut u8, // the block of memory allocated for the vector
     priv iter: Items<'static, T>
@@ -0,0 +1,656 @@
+#[no_std];
+
+extern mod core;
+
+use core::clone::Clone;
+use core::container::Container;
+use core::concurrent::{Queue, BoundedQueue, BoundedPriorityQueue, DelayQueue, RingQueue, Select};
+use core::concurrent::{ConcurrentHashMap, RwLockHashMap, ShardMap, ConcurrentLruCache};
+use core::concurrent::{channel, sync_channel, broadcast, promise};
+use core::concurrent::{Received, Message, Lagged, Empty, Disconnected};
+use core::fail::abort;
+use core::iter::Iterator;
+use core::ignore;
+use core::option::{Some, None};
+use core::thread::{Pool, spawn};
+use core::time::{Time, monotonic, sleep};
+use core::vec::Vec;
+
+fn test_ring_queue() {
+    let queue = RingQueue::<int>::new(3);
+    if queue.capacity() != 4 { abort() }
+    if queue.try_pop().is_some() { abort() }
+
+    let mut i = 0;
+    while i < 4 {
+        if queue.try_push(i).is_some() { abort() }
+        i += 1;
+    }
+    if queue.try_push(4).get() != 4 { abort() }
+    if queue.push_timeout(4, Time::from_seconds(0)).get() != 4 { abort() }
+
+    i = 0;
+    while i < 4 {
+        if queue.try_pop().get() != i { abort() }
+        i += 1;
+    }
+    if queue.try_pop().is_some() { abort() }
+    if queue.pop_timeout(Time::from_seconds(0)).is_some() { abort() }
+
+    // Wrap around the buffer a few times.
+    i = 0;
+    while i < 10 {
+        queue.push(i);
+        queue.push(i + 1);
+        if queue.pop() != i || queue.pop() != i + 1 { abort() }
+        i += 1;
+    }
+}
+
+fn test_ring_queue_threads() {
+    static PRODUCERS: int = 4;
+    static CONSUMERS: int = 4;
+    static N: int = 10000;
+
+    // A small capacity makes both producers and consumers block.
+    let queue = RingQueue::<int>::new(8);
+
+    let mut consumers = Vec::new();
+    let mut i = 0;
+    while i < CONSUMERS {
+        let recv_queue = queue.clone();
+        consumers.push(spawn(proc() {
+            let mut sum = 0;
+            loop {
+                match recv_queue.pop() {
+                    -1 => break,
+                    x => sum += x
+                }
+            }
+            sum
+        }));
+        i += 1;
+    }
+
+    let mut producers = Vec::new();
+    i = 0;
+    while i < PRODUCERS {
+        let send_queue = queue.clone();
+        producers.push(spawn(proc() {
+            let mut i = 0;
+            while i < N {
+                send_queue.push(i);
+                i += 1;
+            }
+        }));
+        i += 1;
+    }
+
+    while producers.pop().is_some() {}
+
+    i = 0;
+    while i < CONSUMERS {
+        queue.push(-1);
+        i += 1;
+    }
+
+    let mut sum = 0;
+    loop {
+        match consumers.pop() {
+            Some(consumer) => sum += *consumer.join(),
+            None => break
+        }
+    }
+    if sum != PRODUCERS * N * (N - 1) / 2 { abort() }
+}
+
+fn test_channel() {
+    let (tx, rx) = channel::<int>();
+    let tx2 = tx.clone();
+    if tx.send(1).is_some() || tx2.send(2).is_some() { abort() }
+    if rx.try_recv().get() != 1 { abort() }
+    ignore(tx);
+    if rx.is_disconnected() { abort() }
+    if rx.recv_timeout(Time::from_seconds(0)).get() != 2 { abort() }
+    ignore(tx2);
+    if !rx.is_disconnected() { abort() }
+    if rx.recv().is_some() { abort() }
+
+    let (tx, rx) = channel::<int>();
+    ignore(rx);
+    if !tx.is_disconnected() { abort() }
+    if tx.send(1).get() != 1 { abort() }
+
+    // A zero maximum holds a single value.
+    let (tx, rx) = sync_channel::<int>(0);
+    if tx.send(1).is_some() || tx.try_send(2).get() != 2 { abort() }
+    if rx.recv().get() != 1 || tx.try_send(3).is_some() { abort() }
+}
+
+fn test_channel_threads() {
+    let (tx, rx) = sync_channel::<int>(4);
+    let mut producers = Vec::new();
+    let mut i = 0;
+    while i < 4 {
+        let tx = tx.clone();
+        producers.push(spawn(proc() {
+            let mut i = 0;
+            while i < 1000 {
+                if tx.send(i).is_some() { abort() }
+                i += 1;
+            }
+        }));
+        i += 1;
+    }
+    ignore(tx);
+
+    // The loop ends once every producer has exited and dropped its sender.
+    let mut sum = 0;
+    loop {
+        match rx.recv() {
+            Some(x) => sum += x,
+            None => break
+        }
+    }
+    if sum != 4 * 999 * 1000 / 2 { abort() }
+
+    // A sender blocked on a full channel is woken up when the receiver goes away.
+    let (tx, rx) = sync_channel::<int>(1);
+    if tx.send(0).is_some() { abort() }
+    let sender = spawn(proc() {
+        tx.send(1)
+    });
+    ignore(rx);
+    if sender.join().get() != 1 { abort() }
+}
+
+fn test_select() {
+    let control = Queue::<int>::new();
+    let data = BoundedQueue::<int>::new(4);
+
+    let mut select = Select::new();
+    let a = select.add(&control);
+    let b = select.add(&data);
+    if a != 0 || b != 1 { abort() }
+
+    if select.wait_timeout(Time::from_seconds(0)).is_some() { abort() }
+
+    data.push(1);
+    if select.wait() != b { abort() }
+    control.push(2);
+    if select.wait() != a { abort() }
+    if control.try_pop().get() != 2 || data.try_pop().get() != 1 { abort() }
+
+    // Wake up from a push on another thread.
+    let send_data = data.clone();
+    let producer = spawn(proc() {
+        sleep(Time::from_seconds(1));
+        send_data.push(3);
+    });
+    if select.wait_timeout(Time::from_seconds(10)).get() != b { abort() }
+    if data.pop().get() != 3 { abort() }
+    ignore(producer);
+}
+
+fn test_close() {
+    let queue = BoundedQueue::<int>::new(2);
+    if queue.push(1).is_some() || queue.push(2).is_some() { abort() }
+
+    // Both a blocked producer and the producers after `close` get their item back.
+    let send_queue = queue.clone();
+    let producer = spawn(proc() {
+        send_queue.push(3)
+    });
+    sleep(Time::from_seconds(1));
+    queue.close();
+    if !queue.is_closed() { abort() }
+    if producer.join().get() != 3 { abort() }
+    if queue.try_push(4).get() != 4 { abort() }
+
+    if queue.pop().get() != 1 || queue.pop().get() != 2 { abort() }
+    if queue.pop().is_some() || queue.pop_timeout(Time::from_seconds(10)).is_some() { abort() }
+
+    // A blocked consumer is woken up.
+    let queue = BoundedPriorityQueue::<int>::new(2);
+    let recv_queue = queue.clone();
+    let consumer = spawn(proc() {
+        recv_queue.pop()
+    });
+    sleep(Time::from_seconds(1));
+    queue.close();
+    if consumer.join().is_some() { abort() }
+    if queue.push(1).get() != 1 { abort() }
+}
+
+fn test_batch() {
+    let queue = Queue::<int>::new();
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < 10 {
+        items.push(i);
+        i += 1;
+    }
+    queue.push_all(items.move_iter());
+
+    let mut out = Vec::new();
+    if queue.drain_into(&mut out, 4) != 4 || out.len() != 4 || out.as_slice()[3] != 3 { abort() }
+    let batch = queue.pop_batch(100, Time::from_seconds(1));
+    if batch.len() != 6 || batch.as_slice()[0] != 4 || batch.as_slice()[5] != 9 { abort() }
+    if queue.drain_into(&mut out, 4) != 0 { abort() }
+    if queue.pop_batch(4, Time::from_seconds(0)).len() != 0 { abort() }
+
+    // A batch wakes every blocked consumer it can satisfy.
+    let mut consumers = Vec::new();
+    i = 0;
+    while i < 3 {
+        let recv_queue = queue.clone();
+        consumers.push(spawn(proc() recv_queue.pop()));
+        i += 1;
+    }
+    sleep(Time::from_seconds(1));
+    queue.push_all(Vec::from_elem(3, 1).move_iter());
+    let mut sum = 0;
+    loop {
+        match consumers.pop() {
+            Some(consumer) => sum += *consumer.join(),
+            None => break
+        }
+    }
+    if sum != 3 { abort() }
+
+    // A producer blocked on a full queue continues as the consumer drains it.
+    let queue = BoundedPriorityQueue::<int>::new(4);
+    let send_queue = queue.clone();
+    let producer = spawn(proc() {
+        let mut items = Vec::new();
+        let mut i = 0;
+        while i < 100 {
+            items.push(i);
+            i += 1;
+        }
+        send_queue.push_all(items.move_iter())
+    });
+    let mut total = 0;
+    let mut count = 0;
+    while count < 100 {
+        let batch = queue.pop_batch(3, Time::from_seconds(10));
+        if batch.len() == 0 || batch.len() > 3 { abort() }
+        for x in batch.move_iter() {
+            total += x;
+            count += 1;
+        }
+    }
+    if producer.join().is_some() || total != 99 * 100 / 2 { abort() }
+
+    // Pushing stops once the queue is closed, handing back the values which were not pushed.
+    let queue = BoundedQueue::<int>::new(2);
+    let send_queue = queue.clone();
+    let producer = spawn(proc() {
+        let mut items = Vec::new();
+        let mut i = 0;
+        while i < 5 {
+            items.push(i);
+            i += 1;
+        }
+        match send_queue.push_all(items.move_iter()) {
+            Some((item, rest)) => {
+                let mut left = Vec::new();
+                left.push(item);
+                for x in rest {
+                    left.push(x);
+                }
+                left
+            }
+            None => abort()
+        }
+    });
+    sleep(Time::from_seconds(1));
+    queue.close();
+    let left = producer.join();
+    if left.len() != 3 || left.as_slice()[0] != 2 || left.as_slice()[2] != 4 { abort() }
+    let batch = queue.pop_batch(4, Time::from_seconds(10));
+    if batch.len() != 2 || batch.as_slice()[1] != 1 { abort() }
+    match queue.push_all(Vec::from_elem(3, 1).move_iter()) {
+        Some((1, rest)) => {
+            let mut count = 0;
+            for _ in rest {
+                count += 1;
+            }
+            if count != 2 { abort() }
+        }
+        _ => abort()
+    }
+    if queue.pop_batch(2, Time::from_seconds(10)).len() != 0 { abort() }
+
+    // A consumer blocked in `Select` is woken up each time a long batch fills the queue.
+    let queue = BoundedQueue::<int>::new(4);
+    let send_queue = queue.clone();
+    let producer = spawn(proc() {
+        let mut items = Vec::new();
+        let mut i = 0;
+        while i < 100 {
+            items.push(i);
+            i += 1;
+        }
+        send_queue.push_all(items.move_iter())
+    });
+    let mut select = Select::new();
+    select.add(&queue);
+    let mut out = Vec::new();
+    while out.len() < 100 {
+        if select.wait_timeout(Time::from_seconds(10)).is_none() { abort() }
+        queue.drain_into(&mut out, 100);
+    }
+    if producer.join().is_some() || out.as_slice()[99] != 99 { abort() }
+}
+
+fn test_delay_queue() {
+    let queue = DelayQueue::<int>::new();
+    queue.push_after(2, Time::from_seconds(2));
+    queue.push_after(1, Time::from_seconds(1));
+    queue.push(0, monotonic());
+    if queue.try_pop().get() != 0 || queue.try_pop().is_some() { abort() }
+    if queue.pop_timeout(Time::from_seconds(0)).is_some() { abort() }
+    if queue.pop() != 1 { abort() }
+    if queue.pop_timeout(Time::from_seconds(10)).get() != 2 { abort() }
+    if queue.pop_timeout(Time::from_seconds(1)).is_some() { abort() }
+
+    // A consumer waiting for a late item is woken up by an earlier one.
+    queue.push_after(1, Time::from_seconds(60));
+    let recv_queue = queue.clone();
+    let consumer = spawn(proc() recv_queue.pop());
+    sleep(Time::from_seconds(1));
+    queue.push_after(2, Time::from_seconds(0));
+    if *consumer.join() != 2 { abort() }
+    if queue.try_pop().is_some() { abort() }
+}
+
+fn message(received: Received<int>) -> int {
+    match received {
+        Message(x) => x,
+        _ => abort()
+    }
+}
+
+fn test_broadcast() {
+    let publisher = broadcast::<int>(4);
+    publisher.send(0);
+    let mut first = publisher.subscribe();
+    match first.try_recv() { Empty => (), _ => abort() }
+    publisher.send(1);
+    let mut second = publisher.subscribe();
+    publisher.send(2);
+
+    // Each subscriber starts with the messages published after it subscribed.
+    if message(first.recv()) != 1 || message(first.recv()) != 2 { abort() }
+    if message(second.try_recv()) != 2 { abort() }
+    match second.recv_timeout(Time::from_seconds(1)) { Empty => (), _ => abort() }
+
+    // A slow subscriber is told how many messages it missed.
+    let mut i = 3;
+    while i < 10 {
+        publisher.send(i);
+        i += 1;
+    }
+    match second.recv() { Lagged(3) => (), _ => abort() }
+    if message(second.recv()) != 6 { abort() }
+    let mut third = second.clone();
+    if message(second.recv()) != 7 || message(third.recv()) != 7 { abort() }
+
+    // Every subscriber receives every message, then the disconnection.
+    let mut workers = Vec::new();
+    i = 0;
+    while i < 4 {
+        let mut subscriber = publisher.subscribe();
+        workers.push(spawn(proc() {
+            let mut sum = 0;
+            loop {
+                match subscriber.recv() {
+                    Message(x) => sum += x,
+                    Disconnected => break,
+                    _ => abort()
+                }
+            }
+            sum
+        }));
+        i += 1;
+    }
+    sleep(Time::from_seconds(1));
+    let other = publisher.clone();
+    publisher.send(1);
+    other.send(2);
+    ignore(publisher);
+    sleep(Time::from_seconds(1));
+    other.send(3);
+    ignore(other);
+    loop {
+        match workers.pop() {
+            Some(worker) => if *worker.join() != 6 { abort() },
+            None => break
+        }
+    }
+    match third.recv() { Lagged(1) => (), _ => abort() }
+    if message(third.recv()) != 9 || message(third.recv()) != 1 { abort() }
+    third.try_recv();
+    third.try_recv();
+    match third.try_recv() { Disconnected => (), _ => abort() }
+}
+
+fn test_promise() {
+    let (slot, future) = promise::<int>();
+    if future.try_get().is_some() || future.get_timeout(Time::from_seconds(1)).is_some() { abort() }
+    slot.set(1);
+    if future.get().get() != 1 || future.try_get().is_some() || future.get().is_some() { abort() }
+
+    // Dropping an unset promise wakes up the future.
+    let (slot, future) = promise::<int>();
+    let waiter = spawn(proc() future.get());
+    sleep(Time::from_seconds(1));
+    ignore(slot);
+    if waiter.join().is_some() { abort() }
+
+    let pool = Pool::new(4);
+    let mut futures = Vec::new();
+    let mut i = 0;
+    while i < 16 {
+        futures.push(pool.execute(proc() i * 2));
+        i += 1;
+    }
+    let mut sum = 0;
+    for future in futures.move_iter() {
+        sum += future.get_timeout(Time::from_seconds(10)).get();
+    }
+    if sum != 15 * 16 { abort() }
+}
+
+fn test_map_with() {
+    let map = ConcurrentHashMap::<int, Vec<int>>::new();
+    map.swap(1, Vec::from_elem(10, 1));
+    if map.with(&1, |v| v.len()).get() != 10 { abort() }
+    if map.with_mut(&1, |v| v.push(2)).is_none() { abort() }
+    if map.with(&1, |v| v.as_slice()[10]).get() != 2 { abort() }
+    if map.with(&2, |v| v.len()).is_some() || map.with_mut(&2, |v| v.pop()).is_some() { abort() }
+
+    let map = ShardMap::<int, int>::new(4);
+    map.swap(1, 10);
+    if map.with_mut(&1, |v| { *v += 1; *v }).get() != 11 { abort() }
+    if map.with(&1, |v| *v * 2).get() != 22 { abort() }
+    if map.with(&2, |v| *v).is_some() { abort() }
+}
+
+fn test_rwlock_hash_map() {
+    let map = RwLockHashMap::<int, int>::new();
+    let mut i = 0;
+    while i < 100 {
+        map.swap(i, i);
+        i += 1;
+    }
+    if map.find(&5).get() != 5 || map.find(&100).is_some() { abort() }
+
+    // Concurrent readers and a writer incrementing every value.
+    let mut readers = Vec::new();
+    i = 0;
+    while i < 4 {
+        let map = map.clone();
+        readers.push(spawn(proc() {
+            let mut i = 0;
+            while i < 100 {
+                if map.with(&i, |v| *v).get() < i { abort() }
+                i += 1;
+            }
+        }));
+        i += 1;
+    }
+    i = 0;
+    while i < 100 {
+        map.with_mut(&i, |v| *v += 1);
+        i += 1;
+    }
+    while readers.pop().is_some() {}
+
+    if map.with(&99, |v| *v).get() != 100 { abort() }
+    if map.pop(&99).get() != 100 || map.find(&99).is_some() { abort() }
+}
+
+fn test_map_compute() {
+    let map = ConcurrentHashMap::<int, int>::new();
+    if map.find_or_insert_with(1, |k| *k * 10) != 10 { abort() }
+    if map.find_or_insert_with(1, |_| abort()) != 10 { abort() }
+    if map.insert_or_update_with(2, 1, |_, v| *v += 1) != 1 { abort() }
+    if map.insert_or_update_with(2, 1, |_, v| *v += 1) != 2 { abort() }
+
+    if map.compute(3, |v| { if v.is_some() { abort() } Some(3) }).is_some() { abort() }
+    if map.compute(3, |v| v.map(|v| *v + 1)).get() != 3 { abort() }
+    if map.compute(3, |_| None).get() != 4 { abort() }
+    if map.find(&3).is_some() || map.compute(3, |_| None).is_some() { abort() }
+
+    if map.remove_if(&1, |v| *v != 10).is_some() { abort() }
+    if map.remove_if(&1, |v| *v == 10).get() != 10 || map.find(&1).is_some() { abort() }
+    if map.remove_if(&1, |_| true).is_some() { abort() }
+
+    // Concurrent counters lose no updates.
+    let map = ShardMap::<int, int>::new(4);
+    let mut workers = Vec::new();
+    let mut i = 0;
+    while i < 4 {
+        let map = map.clone();
+        workers.push(spawn(proc() {
+            let mut i = 0;
+            while i < 1000 {
+                map.insert_or_update_with(i % 10, 1, |_, v| *v += 1);
+                map.compute(-1, |v| Some(v.map_or(1, |v| *v + 1)));
+                i += 1;
+            }
+        }));
+        i += 1;
+    }
+    while workers.pop().is_some() {}
+
+    i = 0;
+    while i < 10 {
+        if map.find(&i).get() != 400 { abort() }
+        i += 1;
+    }
+    if map.find(&-1).get() != 4000 { abort() }
+    if map.remove_if(&-1, |v| *v == 4000).get() != 4000 { abort() }
+    if map.find_or_insert_with(-1, |_| 0) != 0 { abort() }
+}
+
+fn test_shard_map_bulk() {
+    let map = ShardMap::<int, int>::new(8);
+    if map.len() != 0 || map.snapshot().next().is_some() { abort() }
+
+    let mut i = 0;
+    while i < 100 {
+        map.swap(i, i * 2);
+        i += 1;
+    }
+    if map.len() != 100 { abort() }
+
+    let mut sum = 0;
+    let mut count = 0;
+    map.for_each(|k, v| {
+        if *v != *k * 2 { abort() }
+        sum += *k;
+        count += 1;
+    });
+    if sum != 99 * 100 / 2 || count != 100 { abort() }
+
+    sum = 0;
+    count = 0;
+    for (k, v) in map.snapshot() {
+        if v != k * 2 { abort() }
+        sum += k;
+        count += 1;
+    }
+    if sum != 99 * 100 / 2 || count != 100 { abort() }
+
+    map.retain(|k, v| {
+        *v += 1;
+        *k % 2 == 0
+    });
+    if map.len() != 50 || map.find(&1).is_some() || map.find(&2).get() != 5 { abort() }
+
+    map.clear();
+    if map.len() != 0 || map.find(&2).is_some() { abort() }
+}
+
+fn test_lru_cache() {
+    // A single shard behaves like `LruCache`.
+    let cache = ConcurrentLruCache::<int, int>::new(1, 2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    if cache.get(&1).get() != 10 { abort() }
+    cache.put(3, 30);
+    if cache.get(&2).is_some() || cache.get(&3).get() != 30 { abort() }
+    if cache.pop(&1).get() != 10 || cache.len() != 1 { abort() }
+
+    let stats = cache.stats();
+    if stats.hits != 2 || stats.misses != 1 || stats.evictions != 1 { abort() }
+
+    // The capacity applies per shard.
+    let cache = ConcurrentLruCache::<int, int>::new(4, 8);
+    let mut workers = Vec::new();
+    let mut i = 0;
+    while i < 4 {
+        let cache = cache.clone();
+        workers.push(spawn(proc() {
+            let mut j = 0;
+            while j < 1000 {
+                let k = i * 1000 + j;
+                cache.put(k, k);
+                match cache.get(&k) {
+                    Some(v) => if v != k { abort() },
+                    None => ()
+                }
+                j += 1;
+            }
+        }));
+        i += 1;
+    }
+    while workers.pop().is_some() {}
+
+    if cache.len() > 32 { abort() }
+    let stats = cache.stats();
+    if stats.hits + stats.misses != 4000 || stats.evictions != 4000 - cache.len() { abort() }
+}
+
+#[start]
+fn main(_: int, _: **u8) -> int {
+    test_ring_queue();
+    test_ring_queue_threads();
+    test_channel();
+    test_channel_threads();
+    test_select();
+    test_close();
+    test_batch();
+    test_delay_queue();
+    test_broadcast();
+    test_promise();
+    test_map_with();
+    test_rwlock_hash_map();
+    test_map_compute();
+    test_shard_map_bulk();
+    test_lru_cache();
+    0
+}
@@ -13,9 +13,16 @@
 
 extern mod core;
 
-use core::hash::{Hash, HashBytes, State};
+use core::container::Container;
+use core::hash::{Hash, HashBytes, Hasher, State, FnvState, MulState, HashMap, HashSet};
+use core::hash::{Sip13State, Sip128State, HalfSipState};
+use core::hash::{Occupied, Vacant, random_keys};
+use core::linked_hash_map::LinkedHashMap;
+use core::lru::LruCache;
+use core::iter::{Iterator, DoubleEndedIterator};
 use core::fail::abort;
 use core::vec::Vec;
+use core::option::{Some, None};
 
 macro_rules! u8to64_le (
     ($buf:expr, $i:expr) =>
@@ -29,6 +36,14 @@ macro_rules! u8to64_le (
      $buf[7+$i] as u64 << 56)
 )
 
+macro_rules! u8to32_le (
+    ($buf:expr, $i:expr) =>
+    ($buf[0+$i] as u32 |
+     $buf[1+$i] as u32 << 8 |
+     $buf[2+$i] as u32 << 16 |
+     $buf[3+$i] as u32 << 24)
+)
+
 // Hash just the bytes of the slice, without length prefix
 struct Bytes<'a>(&'a [u8]);
 
@@ -134,8 +149,750 @@ fn test_siphash() {
     }
 }
 
+fn test_siphash_1_3() {
+    let vecs : [[u8, ..8], ..64] = [
+        [ 0xdc, 0xc4, 0x0f, 0x05, 0x58, 0x01, 0xac, 0xab, ],
+        [ 0x93, 0xca, 0x57, 0x7d, 0xf3, 0x9b, 0xf4, 0xc9, ],
+        [ 0x4d, 0xd4, 0xc7, 0x4d, 0x02, 0x9b, 0xcb, 0x82, ],
+        [ 0xfb, 0xf7, 0xdd, 0xe7, 0xb8, 0x0a, 0xf8, 0x8b, ],
+        [ 0x28, 0x83, 0xd3, 0x88, 0x60, 0x57, 0x75, 0xcf, ],
+        [ 0x67, 0x3b, 0x53, 0x49, 0x2f, 0xd5, 0xf9, 0xde, ],
+        [ 0xa7, 0x22, 0x9f, 0xc5, 0x50, 0x2b, 0x0d, 0xc5, ],
+        [ 0x40, 0x11, 0xb1, 0x9b, 0x98, 0x7d, 0x92, 0xd3, ],
+        [ 0x8e, 0x9a, 0x29, 0x8d, 0x11, 0x95, 0x90, 0x36, ],
+        [ 0xe4, 0x3d, 0x06, 0x6c, 0xb3, 0x8e, 0xa4, 0x25, ],
+        [ 0x7f, 0x09, 0xff, 0x92, 0xee, 0x85, 0xde, 0x79, ],
+        [ 0x52, 0xc3, 0x4d, 0xf9, 0xc1, 0x18, 0xc1, 0x70, ],
+        [ 0xa2, 0xd9, 0xb4, 0x57, 0xb1, 0x84, 0xa3, 0x78, ],
+        [ 0xa7, 0xff, 0x29, 0x12, 0x0c, 0x76, 0x6f, 0x30, ],
+        [ 0x34, 0x5d, 0xf9, 0xc0, 0x11, 0xa1, 0x5a, 0x60, ],
+        [ 0x56, 0x99, 0x51, 0x2a, 0x6d, 0xd8, 0x20, 0xd3, ],
+        [ 0x66, 0x8b, 0x90, 0x7d, 0x1a, 0xdd, 0x4f, 0xcc, ],
+        [ 0x0c, 0xd8, 0xdb, 0x63, 0x90, 0x68, 0xf2, 0x9c, ],
+        [ 0x3e, 0xe6, 0x73, 0xb4, 0x9c, 0x38, 0xfc, 0x8f, ],
+        [ 0x1c, 0x7d, 0x29, 0x8d, 0xe5, 0x9d, 0x1f, 0xf2, ],
+        [ 0x40, 0xe0, 0xcc, 0xa6, 0x46, 0x2f, 0xdc, 0xc0, ],
+        [ 0x44, 0xf8, 0x45, 0x2b, 0xfe, 0xab, 0x92, 0xb9, ],
+        [ 0x2e, 0x87, 0x20, 0xa3, 0x9b, 0x7b, 0xfe, 0x7f, ],
+        [ 0x23, 0xc1, 0xe6, 0xda, 0x7f, 0x0e, 0x5a, 0x52, ],
+        [ 0x8c, 0x9c, 0x34, 0x67, 0xb2, 0xae, 0x64, 0xf4, ],
+        [ 0x79, 0x09, 0x5b, 0x70, 0x28, 0x59, 0xcd, 0x45, ],
+        [ 0xa5, 0x13, 0x99, 0xca, 0xe3, 0x35, 0x3e, 0x3a, ],
+        [ 0x35, 0x3b, 0xde, 0x4a, 0x4e, 0xc7, 0x1d, 0xa9, ],
+        [ 0x0d, 0xd0, 0x6c, 0xef, 0x02, 0xed, 0x0b, 0xfb, ],
+        [ 0xf4, 0xe1, 0xb1, 0x4a, 0xb4, 0x3c, 0xd9, 0x88, ],
+        [ 0x63, 0xe6, 0xc5, 0x43, 0xd6, 0x11, 0x0f, 0x54, ],
+        [ 0xbc, 0xd1, 0x21, 0x8c, 0x1f, 0xdd, 0x70, 0x23, ],
+        [ 0x0d, 0xb6, 0xa7, 0x16, 0x6c, 0x7b, 0x15, 0x81, ],
+        [ 0xbf, 0xf9, 0x8f, 0x7a, 0xe5, 0xb9, 0x54, 0x4d, ],
+        [ 0x3e, 0x75, 0x2a, 0x1f, 0x78, 0x12, 0x9f, 0x75, ],
+        [ 0x91, 0x6b, 0x18, 0xbf, 0xbe, 0xa3, 0xa1, 0xce, ],
+        [ 0x06, 0x62, 0xa2, 0xad, 0xd3, 0x08, 0xf5, 0x2c, ],
+        [ 0x57, 0x30, 0xc3, 0xa3, 0x2d, 0x1c, 0x10, 0xb6, ],
+        [ 0xa1, 0x36, 0x3a, 0xae, 0x96, 0x74, 0xf4, 0xb3, ],
+        [ 0x92, 0x83, 0x10, 0x7b, 0x54, 0x57, 0x6b, 0x62, ],
+        [ 0x31, 0x15, 0xe4, 0x99, 0x32, 0x36, 0xd2, 0xc1, ],
+        [ 0x44, 0xd9, 0x1a, 0x3f, 0x92, 0xc1, 0x7c, 0x66, ],
+        [ 0x25, 0x88, 0x13, 0xc8, 0xfe, 0x4f, 0x70, 0x65, ],
+        [ 0xa6, 0x49, 0x89, 0xc2, 0xd1, 0x80, 0xf2, 0x24, ],
+        [ 0x6b, 0x87, 0xf8, 0xfa, 0xed, 0x1c, 0xca, 0xc2, ],
+        [ 0x96, 0x21, 0x04, 0x9f, 0xfc, 0x4b, 0x16, 0xc2, ],
+        [ 0x23, 0xd6, 0xb1, 0x68, 0x93, 0x9c, 0x6e, 0xa1, ],
+        [ 0xfd, 0x14, 0x51, 0x8b, 0x9c, 0x16, 0xfb, 0x49, ],
+        [ 0x46, 0x4c, 0x07, 0xdf, 0xf8, 0x43, 0x31, 0x9f, ],
+        [ 0xb3, 0x86, 0xcc, 0x12, 0x24, 0xaf, 0xfd, 0xc6, ],
+        [ 0x8f, 0x09, 0x52, 0x0a, 0xd1, 0x49, 0xaf, 0x7e, ],
+        [ 0x9a, 0x2f, 0x29, 0x9d, 0x55, 0x13, 0xf3, 0x1c, ],
+        [ 0x12, 0x1f, 0xf4, 0xa2, 0xdd, 0x30, 0x4a, 0xc4, ],
+        [ 0xd0, 0x1e, 0xa7, 0x43, 0x89, 0xe9, 0xfa, 0x36, ],
+        [ 0xe6, 0xbc, 0xf0, 0x73, 0x4c, 0xb3, 0x8f, 0x31, ],
+        [ 0x80, 0xe9, 0xa7, 0x70, 0x36, 0xbf, 0x7a, 0xa2, ],
+        [ 0x75, 0x6d, 0x3c, 0x24, 0xdb, 0xc0, 0xbc, 0xb4, ],
+        [ 0x13, 0x15, 0xb7, 0xfd, 0x52, 0xd8, 0xf8, 0x23, ],
+        [ 0x08, 0x8a, 0x7d, 0xa6, 0x4d, 0x5f, 0x03, 0x8f, ],
+        [ 0x48, 0xf1, 0xe8, 0xb7, 0xe5, 0xd0, 0x9c, 0xd8, ],
+        [ 0xee, 0x44, 0xa6, 0xf7, 0xbc, 0xe6, 0xf4, 0xf6, ],
+        [ 0xf2, 0x37, 0x18, 0x0f, 0xd8, 0x9a, 0xc5, 0xae, ],
+        [ 0xe0, 0x94, 0x66, 0x4b, 0x15, 0xf6, 0xb2, 0xc3, ],
+        [ 0xa8, 0xb3, 0xbb, 0xb7, 0x62, 0x90, 0x19, 0x9d, ]
+    ];
+
+    let k0 = 0x_07_06_05_04_03_02_01_00_u64;
+    let k1 = 0x_0f_0e_0d_0c_0b_0a_09_08_u64;
+    let mut buf = Vec::new();
+    let mut t = 0;
+    let mut stream_inc = Sip13State::new(k0, k1);
+    let mut stream_full = Sip13State::new(k0, k1);
+
+    while t < 64 {
+        stream_full.reset();
+        stream_full.write(buf.as_slice());
+        let f = stream_full.result();
+        let i = stream_inc.result();
+
+        let v = u8to64_le!(vecs[t], 0);
+
+        if f != i || f != v { abort() }
+
+        buf.push(t as u8);
+        stream_inc.write([t as u8]);
+
+        t += 1;
+    }
+}
+
+fn test_siphash_128() {
+    let vecs : [[u8, ..16], ..64] = [
+        [ 0xa3, 0x81, 0x7f, 0x04, 0xba, 0x25, 0xa8, 0xe6,
+          0x6d, 0xf6, 0x72, 0x14, 0xc7, 0x55, 0x02, 0x93, ],
+        [ 0xda, 0x87, 0xc1, 0xd8, 0x6b, 0x99, 0xaf, 0x44,
+          0x34, 0x76, 0x59, 0x11, 0x9b, 0x22, 0xfc, 0x45, ],
+        [ 0x81, 0x77, 0x22, 0x8d, 0xa4, 0xa4, 0x5d, 0xc7,
+          0xfc, 0xa3, 0x8b, 0xde, 0xf6, 0x0a, 0xff, 0xe4, ],
+        [ 0x9c, 0x70, 0xb6, 0x0c, 0x52, 0x67, 0xa9, 0x4e,
+          0x5f, 0x33, 0xb6, 0xb0, 0x29, 0x85, 0xed, 0x51, ],
+        [ 0xf8, 0x81, 0x64, 0xc1, 0x2d, 0x9c, 0x8f, 0xaf,
+          0x7d, 0x0f, 0x6e, 0x7c, 0x7b, 0xcd, 0x55, 0x79, ],
+        [ 0x13, 0x68, 0x87, 0x59, 0x80, 0x77, 0x6f, 0x88,
+          0x54, 0x52, 0x7a, 0x07, 0x69, 0x0e, 0x96, 0x27, ],
+        [ 0x14, 0xee, 0xca, 0x33, 0x8b, 0x20, 0x86, 0x13,
+          0x48, 0x5e, 0xa0, 0x30, 0x8f, 0xd7, 0xa1, 0x5e, ],
+        [ 0xa1, 0xf1, 0xeb, 0xbe, 0xd8, 0xdb, 0xc1, 0x53,
+          0xc0, 0xb8, 0x4a, 0xa6, 0x1f, 0xf0, 0x82, 0x39, ],
+        [ 0x3b, 0x62, 0xa9, 0xba, 0x62, 0x58, 0xf5, 0x61,
+          0x0f, 0x83, 0xe2, 0x64, 0xf3, 0x14, 0x97, 0xb4, ],
+        [ 0x26, 0x44, 0x99, 0x06, 0x0a, 0xd9, 0xba, 0xab,
+          0xc4, 0x7f, 0x8b, 0x02, 0xbb, 0x6d, 0x71, 0xed, ],
+        [ 0x00, 0x11, 0x0d, 0xc3, 0x78, 0x14, 0x69, 0x56,
+          0xc9, 0x54, 0x47, 0xd3, 0xf3, 0xd0, 0xfb, 0xba, ],
+        [ 0x01, 0x51, 0xc5, 0x68, 0x38, 0x6b, 0x66, 0x77,
+          0xa2, 0xb4, 0xdc, 0x6f, 0x81, 0xe5, 0xdc, 0x18, ],
+        [ 0xd6, 0x26, 0xb2, 0x66, 0x90, 0x5e, 0xf3, 0x58,
+          0x82, 0x63, 0x4d, 0xf6, 0x85, 0x32, 0xc1, 0x25, ],
+        [ 0x98, 0x69, 0xe2, 0x47, 0xe9, 0xc0, 0x8b, 0x10,
+          0xd0, 0x29, 0x93, 0x4f, 0xc4, 0xb9, 0x52, 0xf7, ],
+        [ 0x31, 0xfc, 0xef, 0xac, 0x66, 0xd7, 0xde, 0x9c,
+          0x7e, 0xc7, 0x48, 0x5f, 0xe4, 0x49, 0x49, 0x02, ],
+        [ 0x54, 0x93, 0xe9, 0x99, 0x33, 0xb0, 0xa8, 0x11,
+          0x7e, 0x08, 0xec, 0x0f, 0x97, 0xcf, 0xc3, 0xd9, ],
+        [ 0x6e, 0xe2, 0xa4, 0xca, 0x67, 0xb0, 0x54, 0xbb,
+          0xfd, 0x33, 0x15, 0xbf, 0x85, 0x23, 0x05, 0x77, ],
+        [ 0x47, 0x3d, 0x06, 0xe8, 0x73, 0x8d, 0xb8, 0x98,
+          0x54, 0xc0, 0x66, 0xc4, 0x7a, 0xe4, 0x77, 0x40, ],
+        [ 0xa4, 0x26, 0xe5, 0xe4, 0x23, 0xbf, 0x48, 0x85,
+          0x29, 0x4d, 0xa4, 0x81, 0xfe, 0xae, 0xf7, 0x23, ],
+        [ 0x78, 0x01, 0x77, 0x31, 0xcf, 0x65, 0xfa, 0xb0,
+          0x74, 0xd5, 0x20, 0x89, 0x52, 0x51, 0x2e, 0xb1, ],
+        [ 0x9e, 0x25, 0xfc, 0x83, 0x3f, 0x22, 0x90, 0x73,
+          0x3e, 0x93, 0x44, 0xa5, 0xe8, 0x38, 0x39, 0xeb, ],
+        [ 0x56, 0x8e, 0x49, 0x5a, 0xbe, 0x52, 0x5a, 0x21,
+          0x8a, 0x22, 0x14, 0xcd, 0x3e, 0x07, 0x1d, 0x12, ],
+        [ 0x4a, 0x29, 0xb5, 0x45, 0x52, 0xd1, 0x6b, 0x9a,
+          0x46, 0x9c, 0x10, 0x52, 0x8e, 0xff, 0x0a, 0xae, ],
+        [ 0xc9, 0xd1, 0x84, 0xdd, 0xd5, 0xa9, 0xf5, 0xe0,
+          0xcf, 0x8c, 0xe2, 0x9a, 0x9a, 0xbf, 0x69, 0x1c, ],
+        [ 0x2d, 0xb4, 0x79, 0xae, 0x78, 0xbd, 0x50, 0xd8,
+          0x88, 0x2a, 0x8a, 0x17, 0x8a, 0x61, 0x32, 0xad, ],
+        [ 0x8e, 0xce, 0x5f, 0x04, 0x2d, 0x5e, 0x44, 0x7b,
+          0x50, 0x51, 0xb9, 0xea, 0xcb, 0x8d, 0x8f, 0x6f, ],
+        [ 0x9c, 0x0b, 0x53, 0xb4, 0xb3, 0xc3, 0x07, 0xe8,
+          0x7e, 0xae, 0xe0, 0x86, 0x78, 0x14, 0x1f, 0x66, ],
+        [ 0xab, 0xf2, 0x48, 0xaf, 0x69, 0xa6, 0xea, 0xe4,
+          0xbf, 0xd3, 0xeb, 0x2f, 0x12, 0x9e, 0xeb, 0x94, ],
+        [ 0x06, 0x64, 0xda, 0x16, 0x68, 0x57, 0x4b, 0x88,
+          0xb9, 0x35, 0xf3, 0x02, 0x73, 0x58, 0xae, 0xf4, ],
+        [ 0xaa, 0x4b, 0x9d, 0xc4, 0xbf, 0x33, 0x7d, 0xe9,
+          0x0c, 0xd4, 0xfd, 0x3c, 0x46, 0x7c, 0x6a, 0xb7, ],
+        [ 0xea, 0x5c, 0x7f, 0x47, 0x1f, 0xaf, 0x6b, 0xde,
+          0x2b, 0x1a, 0xd7, 0xd4, 0x68, 0x6d, 0x22, 0x87, ],
+        [ 0x29, 0x39, 0xb0, 0x18, 0x32, 0x23, 0xfa, 0xfc,
+          0x17, 0x23, 0xde, 0x4f, 0x52, 0xc4, 0x3d, 0x35, ],
+        [ 0x7c, 0x39, 0x56, 0xca, 0x5e, 0xea, 0xfc, 0x3e,
+          0x36, 0x3e, 0x9d, 0x55, 0x65, 0x46, 0xeb, 0x68, ],
+        [ 0x77, 0xc6, 0x07, 0x71, 0x46, 0xf0, 0x1c, 0x32,
+          0xb6, 0xb6, 0x9d, 0x5f, 0x4e, 0xa9, 0xff, 0xcf, ],
+        [ 0x37, 0xa6, 0x98, 0x6c, 0xb8, 0x84, 0x7e, 0xdf,
+          0x09, 0x25, 0xf0, 0xf1, 0x30, 0x9b, 0x54, 0xde, ],
+        [ 0xa7, 0x05, 0xf0, 0xe6, 0x9d, 0xa9, 0xa8, 0xf9,
+          0x07, 0x24, 0x1a, 0x2e, 0x92, 0x3c, 0x8c, 0xc8, ],
+        [ 0x3d, 0xc4, 0x7d, 0x1f, 0x29, 0xc4, 0x48, 0x46,
+          0x1e, 0x9e, 0x76, 0xed, 0x90, 0x4f, 0x67, 0x11, ],
+        [ 0x0d, 0x62, 0xbf, 0x01, 0xe6, 0xfc, 0x0e, 0x1a,
+          0x0d, 0x3c, 0x47, 0x51, 0xc5, 0xd3, 0x69, 0x2b, ],
+        [ 0x8c, 0x03, 0x46, 0x8b, 0xca, 0x7c, 0x66, 0x9e,
+          0xe4, 0xfd, 0x5e, 0x08, 0x4b, 0xbe, 0xe7, 0xb5, ],
+        [ 0x52, 0x8a, 0x5b, 0xb9, 0x3b, 0xaf, 0x2c, 0x9c,
+          0x44, 0x73, 0xcc, 0xe5, 0xd0, 0xd2, 0x2b, 0xd9, ],
+        [ 0xdf, 0x6a, 0x30, 0x1e, 0x95, 0xc9, 0x5d, 0xad,
+          0x97, 0xae, 0x0c, 0xc8, 0xc6, 0x91, 0x3b, 0xd8, ],
+        [ 0x80, 0x11, 0x89, 0x90, 0x2c, 0x85, 0x7f, 0x39,
+          0xe7, 0x35, 0x91, 0x28, 0x5e, 0x70, 0xb6, 0xdb, ],
+        [ 0xe6, 0x17, 0x34, 0x6a, 0xc9, 0xc2, 0x31, 0xbb,
+          0x36, 0x50, 0xae, 0x34, 0xcc, 0xca, 0x0c, 0x5b, ],
+        [ 0x27, 0xd9, 0x34, 0x37, 0xef, 0xb7, 0x21, 0xaa,
+          0x40, 0x18, 0x21, 0xdc, 0xec, 0x5a, 0xdf, 0x89, ],
+        [ 0x89, 0x23, 0x7d, 0x9d, 0xed, 0x9c, 0x5e, 0x78,
+          0xd8, 0xb1, 0xc9, 0xb1, 0x66, 0xcc, 0x73, 0x42, ],
+        [ 0x4a, 0x6d, 0x80, 0x91, 0xbf, 0x5e, 0x7d, 0x65,
+          0x11, 0x89, 0xfa, 0x94, 0xa2, 0x50, 0xb1, 0x4c, ],
+        [ 0x0e, 0x33, 0xf9, 0x60, 0x55, 0xe7, 0xae, 0x89,
+          0x3f, 0xfc, 0x0e, 0x3d, 0xcf, 0x49, 0x29, 0x02, ],
+        [ 0xe6, 0x1c, 0x43, 0x2b, 0x72, 0x0b, 0x19, 0xd1,
+          0x8e, 0xc8, 0xd8, 0x4b, 0xdc, 0x63, 0x15, 0x1b, ],
+        [ 0xf7, 0xe5, 0xae, 0xf5, 0x49, 0xf7, 0x82, 0xcf,
+          0x37, 0x90, 0x55, 0xa6, 0x08, 0x26, 0x9b, 0x16, ],
+        [ 0x43, 0x8d, 0x03, 0x0f, 0xd0, 0xb7, 0xa5, 0x4f,
+          0xa8, 0x37, 0xf2, 0xad, 0x20, 0x1a, 0x64, 0x03, ],
+        [ 0xa5, 0x90, 0xd3, 0xee, 0x4f, 0xbf, 0x04, 0xe3,
+          0x24, 0x7e, 0x0d, 0x27, 0xf2, 0x86, 0x42, 0x3f, ],
+        [ 0x5f, 0xe2, 0xc1, 0xa1, 0x72, 0xfe, 0x93, 0xc4,
+          0xb1, 0x5c, 0xd3, 0x7c, 0xae, 0xf9, 0xf5, 0x38, ],
+        [ 0x2c, 0x97, 0x32, 0x5c, 0xbd, 0x06, 0xb3, 0x6e,
+          0xb2, 0x13, 0x3d, 0xd0, 0x8b, 0x3a, 0x01, 0x7c, ],
+        [ 0x92, 0xc8, 0x14, 0x22, 0x7a, 0x6b, 0xca, 0x94,
+          0x9f, 0xf0, 0x65, 0x9f, 0x00, 0x2a, 0xd3, 0x9e, ],
+        [ 0xdc, 0xe8, 0x50, 0x11, 0x0b, 0xd8, 0x32, 0x8c,
+          0xfb, 0xd5, 0x08, 0x41, 0xd6, 0x91, 0x1d, 0x87, ],
+        [ 0x67, 0xf1, 0x49, 0x84, 0xc7, 0xda, 0x79, 0x12,
+          0x48, 0xe3, 0x2b, 0xb5, 0x92, 0x25, 0x83, 0xda, ],
+        [ 0x19, 0x38, 0xf2, 0xcf, 0x72, 0xd5, 0x4e, 0xe9,
+          0x7e, 0x94, 0x16, 0x6f, 0xa9, 0x1d, 0x2a, 0x36, ],
+        [ 0x74, 0x48, 0x1e, 0x96, 0x46, 0xed, 0x49, 0xfe,
+          0x0f, 0x62, 0x24, 0x30, 0x16, 0x04, 0x69, 0x8e, ],
+        [ 0x57, 0xfc, 0xa5, 0xde, 0x98, 0xa9, 0xd6, 0xd8,
+          0x00, 0x64, 0x38, 0xd0, 0x58, 0x3d, 0x8a, 0x1d, ],
+        [ 0x9f, 0xec, 0xde, 0x1c, 0xef, 0xdc, 0x1c, 0xbe,
+          0xd4, 0x76, 0x36, 0x74, 0xd9, 0x57, 0x53, 0x59, ],
+        [ 0xe3, 0x04, 0x0c, 0x00, 0xeb, 0x28, 0xf1, 0x53,
+          0x66, 0xca, 0x73, 0xcb, 0xd8, 0x72, 0xe7, 0x40, ],
+        [ 0x76, 0x97, 0x00, 0x9a, 0x6a, 0x83, 0x1d, 0xfe,
+          0xcc, 0xa9, 0x1c, 0x59, 0x93, 0x67, 0x0f, 0x7a, ],
+        [ 0x58, 0x53, 0x54, 0x23, 0x21, 0xf5, 0x67, 0xa0,
+          0x05, 0xd5, 0x47, 0xa4, 0xf0, 0x47, 0x59, 0xbd, ],
+        [ 0x51, 0x50, 0xd1, 0x77, 0x2f, 0x50, 0x83, 0x4a,
+          0x50, 0x3e, 0x06, 0x9a, 0x97, 0x3f, 0xbd, 0x7c, ]
+    ];
+
+    let k0 = 0x_07_06_05_04_03_02_01_00_u64;
+    let k1 = 0x_0f_0e_0d_0c_0b_0a_09_08_u64;
+    let mut buf = Vec::new();
+    let mut t = 0;
+    let mut stream_inc = Sip128State::new(k0, k1);
+    let mut stream_full = Sip128State::new(k0, k1);
+
+    while t < 64 {
+        stream_full.reset();
+        stream_full.write(buf.as_slice());
+        let (f0, f1) = stream_full.result();
+        let (i0, i1) = stream_inc.result();
+
+        let v0 = u8to64_le!(vecs[t], 0);
+        let v1 = u8to64_le!(vecs[t], 8);
+
+        if f0 != i0 || f1 != i1 || f0 != v0 || f1 != v1 { abort() }
+
+        buf.push(t as u8);
+        stream_inc.write([t as u8]);
+
+        t += 1;
+    }
+}
+
+fn test_halfsiphash() {
+    let vecs : [[u8, ..4], ..64] = [
+        [ 0xa9, 0x35, 0x9f, 0x5b, ],
+        [ 0x27, 0x47, 0x5a, 0xb8, ],
+        [ 0xfa, 0x62, 0xa6, 0x03, ],
+        [ 0x8a, 0xfe, 0xe7, 0x04, ],
+        [ 0x2a, 0x6e, 0x46, 0x89, ],
+        [ 0xc5, 0xfa, 0xb6, 0x69, ],
+        [ 0x58, 0x63, 0xfc, 0x23, ],
+        [ 0x8b, 0xcf, 0x63, 0xc5, ],
+        [ 0xd0, 0xb8, 0x84, 0x8f, ],
+        [ 0xf8, 0x06, 0xe7, 0x79, ],
+        [ 0x94, 0xb0, 0x79, 0x34, ],
+        [ 0x08, 0x08, 0x30, 0x50, ],
+        [ 0x57, 0xf0, 0x87, 0x2f, ],
+        [ 0x77, 0xe6, 0x63, 0xff, ],
+        [ 0xd6, 0xff, 0xf8, 0x7c, ],
+        [ 0x74, 0xfe, 0x2b, 0x97, ],
+        [ 0xd9, 0xb5, 0xac, 0x84, ],
+        [ 0xc4, 0x74, 0x64, 0x5b, ],
+        [ 0x46, 0x5b, 0x8d, 0x9b, ],
+        [ 0x7b, 0xef, 0xe3, 0x87, ],
+        [ 0xe3, 0x4d, 0x10, 0x45, ],
+        [ 0x61, 0x3f, 0x62, 0xb3, ],
+        [ 0x70, 0xf3, 0x67, 0xfe, ],
+        [ 0xe6, 0xad, 0xb8, 0xbd, ],
+        [ 0x27, 0x40, 0x0c, 0x63, ],
+        [ 0x26, 0x78, 0x78, 0x75, ],
+        [ 0x4f, 0x56, 0x7b, 0x5f, ],
+        [ 0x3a, 0xb0, 0xe6, 0x69, ],
+        [ 0xb0, 0x64, 0x40, 0x00, ],
+        [ 0xff, 0x67, 0x0f, 0xb4, ],
+        [ 0x50, 0x9e, 0x33, 0x8b, ],
+        [ 0x5d, 0x58, 0x9f, 0x1a, ],
+        [ 0xfe, 0xe7, 0x21, 0x12, ],
+        [ 0x33, 0x75, 0x32, 0x59, ],
+        [ 0x6a, 0x43, 0x4f, 0x8c, ],
+        [ 0xfe, 0x28, 0xb7, 0x29, ],
+        [ 0xe7, 0x5c, 0xc6, 0xec, ],
+        [ 0x69, 0x7e, 0x8d, 0x54, ],
+        [ 0x63, 0x68, 0x8b, 0x0f, ],
+        [ 0x65, 0x0b, 0x62, 0xb4, ],
+        [ 0xb6, 0xbc, 0x18, 0x40, ],
+        [ 0x5d, 0x07, 0x45, 0x05, ],
+        [ 0x24, 0x42, 0xfd, 0x2e, ],
+        [ 0x7b, 0xb7, 0x86, 0x3a, ],
+        [ 0x77, 0x05, 0xd5, 0x48, ],
+        [ 0xd7, 0x52, 0x08, 0xb1, ],
+        [ 0xb6, 0xd4, 0x99, 0xc8, ],
+        [ 0x08, 0x92, 0x20, 0x2e, ],
+        [ 0x69, 0xe1, 0x2c, 0xe3, ],
+        [ 0x8d, 0xb5, 0x80, 0xe5, ],
+        [ 0x36, 0x97, 0x64, 0xc6, ],
+        [ 0x01, 0x6e, 0x02, 0x04, ],
+        [ 0x3b, 0x85, 0xf3, 0xd4, ],
+        [ 0xfe, 0xdb, 0x66, 0xbe, ],
+        [ 0x1e, 0x69, 0x2a, 0x3a, ],
+        [ 0xc6, 0x89, 0x84, 0xc0, ],
+        [ 0xa5, 0xc5, 0xb9, 0x40, ],
+        [ 0x9b, 0xe9, 0xe8, 0x8c, ],
+        [ 0x7d, 0xbc, 0x81, 0x40, ],
+        [ 0x7c, 0x07, 0x8e, 0xc5, ],
+        [ 0xd4, 0xe7, 0x6c, 0x73, ],
+        [ 0x42, 0x8f, 0xcb, 0xb9, ],
+        [ 0xbd, 0x83, 0x99, 0x7a, ],
+        [ 0x59, 0xea, 0x4a, 0x74, ]
+    ];
+
+    let k0 = 0x_03_02_01_00_u32;
+    let k1 = 0x_07_06_05_04_u32;
+    let mut buf = Vec::new();
+    let mut t = 0;
+    let mut stream_inc = HalfSipState::new(k0, k1);
+    let mut stream_full = HalfSipState::new(k0, k1);
+
+    while t < 64 {
+        stream_full.reset();
+        stream_full.write(buf.as_slice());
+        let f = stream_full.result();
+        let i = stream_inc.result();
+
+        let v = u8to32_le!(vecs[t], 0);
+
+        if f != i || f != v { abort() }
+
+        buf.push(t as u8);
+        stream_inc.write([t as u8]);
+
+        t += 1;
+    }
+}
+
+fn test_fnv() {
+    // With zero keys, the state is plain FNV-1a after hashing 9 zero bytes.
+    let mut s = FnvState::new(0, 0);
+    if s.finish() != 0xe604823a249029bf { abort() }
+    s.write(bytes!("a"));
+    if s.finish() != 0x69d326cc20f7243a { abort() }
+
+    let mut s = FnvState::new(0, 0);
+    s.write(bytes!("foo"));
+    s.write(bytes!("bar"));
+    if s.finish() != 0x8301aff8cac63c86 { abort() }
+
+    // Equal keys and swapped keys do not cancel out.
+    let zero = FnvState::new(0, 0).finish();
+    if FnvState::new(1, 1).finish() == zero || FnvState::new(5, 5).finish() == zero { abort() }
+    if FnvState::new(1, 2).finish() == FnvState::new(2, 1).finish() { abort() }
+    if FnvState::new(1, 2).finish() == FnvState::new(3, 0).finish() { abort() }
+}
+
+fn test_hasher_map() {
+    let mut a = HashMap::with_capacity_and_hasher(FnvState::new(1, 2), 0);
+    let mut b = HashMap::with_capacity_and_hasher(MulState::new(1, 2), 0);
+    let mut i = 0;
+    while i < 1000 {
+        a.swap(i, i);
+        b.swap(i, i);
+        i += 1;
+    }
+    i = 0;
+    while i < 1000 {
+        if *a.find(&i).get() != i || *b.find(&i).get() != i { abort() }
+        i += 1;
+    }
+
+    let x = 10u64;
+    if x.hash_with(&MulState::new(0, 0)) == x.hash_with(&MulState::new(0, 1)) { abort() }
+    if x.hash_with(&State::new(5, 6)) != x.hash(5, 6) { abort() }
+}
+
+fn test_hash_bytes() {
+    if ("ab", "c").hash(0, 0) == ("a", "bc").hash(0, 0) { abort() }
+    if (Some(1), 2).hash(0, 0) == (Some(1), 3).hash(0, 0) { abort() }
+    if Some(0u8).hash(0, 0) == None::<u8>.hash(0, 0) { abort() }
+    if (~5).hash(0, 0) != 5.hash(0, 0) { abort() }
+
+    let mut v = Vec::new();
+    v.push(1);
+    v.push(2);
+    if v.hash(0, 0) != v.as_slice().hash(0, 0) { abort() }
+
+    if (-0.0f64).hash(0, 0) != 0.0f64.hash(0, 0) { abort() }
+    if (0.0f32 / 0.0).hash(0, 0) != (-(0.0f32 / 0.0)).hash(0, 0) { abort() }
+    if 1.0f64.hash(0, 0) == 2.0f64.hash(0, 0) { abort() }
+}
+
+fn test_random_keys() {
+    let (a0, a1) = random_keys();
+    let (b0, b1) = random_keys();
+    if a0 == b0 && a1 == b1 { abort() }
+
+    let mut m = HashMap::new();
+    m.swap(1, 2);
+    if *m.find(&1).get() != 2 { abort() }
+
+    let mut s = HashSet::with_capacity(10);
+    s.insert(1);
+    if !s.contains(&1) { abort() }
+
+    let mut cache = LruCache::with_capacity(1);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    if cache.get(&1).is_some() || *cache.get(&2).get() != 20 { abort() }
+}
+
+fn test_hash_set() {
+    let mut a = HashSet::with_capacity_and_keys(0, 0, 0);
+    let mut b = HashSet::with_capacity_and_keys(0, 0, 0);
+
+    if !a.insert(1) || !a.insert(2) || !a.insert(3) { abort() }
+    if a.insert(3) { abort() }
+    if !b.insert(3) || !b.insert(4) { abort() }
+
+    if a.len() != 3 || !a.contains(&2) || a.contains(&4) { abort() }
+    if a.is_disjoint(&b) || a.is_subset(&b) { abort() }
+
+    let mut n = 0;
+    for x in a.intersection(&b) {
+        if *x != 3 { abort() }
+        n += 1;
+    }
+    if n != 1 { abort() }
+
+    n = 0;
+    for x in a.difference(&b) {
+        if *x != 1 && *x != 2 { abort() }
+        n += 1;
+    }
+    if n != 2 { abort() }
+
+    n = 0;
+    for x in a.symmetric_difference(&b) {
+        if *x == 3 { abort() }
+        n += 1;
+    }
+    if n != 3 { abort() }
+
+    n = 0;
+    for _ in a.union(&b) { n += 1 }
+    if n != 4 { abort() }
+
+    if !b.remove(&4) || b.remove(&4) { abort() }
+    if !b.is_subset(&a) || !a.is_superset(&b) { abort() }
+}
+
+fn test_hash_map_iter() {
+    let mut m = HashMap::with_capacity_and_keys(0, 0, 0);
+    let mut i = 0;
+    while i < 100 {
+        m.swap(i, i * 2);
+        i += 1;
+    }
+
+    let mut n = 0;
+    let mut sum = 0;
+    for (k, v) in m.iter() {
+        if *v != *k * 2 { abort() }
+        sum += *k;
+        n += 1;
+    }
+    if n != 100 || sum != 4950 { abort() }
+
+    for (_, v) in m.mut_iter() {
+        *v += 1;
+    }
+    for (k, v) in m.iter() {
+        if *v != *k * 2 + 1 { abort() }
+    }
+
+    let mut keys = m.keys();
+    let (lower, upper) = keys.size_hint();
+    if lower != 100 || upper.get() != 100 { abort() }
+    keys.next();
+    let (lower, upper) = keys.size_hint();
+    if lower != 99 || upper.get() != 99 { abort() }
+
+    n = 0;
+    for _ in m.values() { n += 1 }
+    if n != 100 { abort() }
+
+    n = 0;
+    for (k, v) in m.move_iter() {
+        if v != k * 2 + 1 { abort() }
+        n += 1;
+    }
+    if n != 100 { abort() }
+}
+
+fn test_hash_map_pop() {
+    let mut m = HashMap::with_capacity_and_keys(0, 0, 0);
+    let mut i = 0;
+    while i < 1000 {
+        m.swap(i, i);
+        i += 1;
+    }
+
+    // Removal shifts the rest of the cluster back, so every remaining key must stay reachable.
+    i = 0;
+    while i < 1000 {
+        if m.pop(&i).get() != i { abort() }
+        if m.pop(&i).is_some() { abort() }
+        let mut j = i + 1;
+        while j < 1000 {
+            if *m.find(&j).get() != j { abort() }
+            j += 37;
+        }
+        i += 3;
+    }
+    if m.len() != 666 { abort() }
+
+    i = 0;
+    while i < 1000 {
+        if (i % 3 == 0) != m.find(&i).is_none() { abort() }
+        i += 1;
+    }
+}
+
+fn test_hash_map_shrink() {
+    let mut m = HashMap::with_capacity_and_keys(0, 0, 0);
+    let initial = m.capacity();
+    let mut i = 0;
+    while i < 1000 {
+        m.swap(i, i);
+        i += 1;
+    }
+
+    // Every entry is visited once, and the survivors stay reachable.
+    let mut visited = 0;
+    m.retain(|k, v| { visited += 1; *v += 1; *k % 2 == 0 });
+    if visited != 1000 || m.len() != 500 { abort() }
+    i = 0;
+    while i < 1000 {
+        match m.find(&i) {
+            Some(v) => if i % 2 != 0 || *v != i + 1 { abort() },
+            None => if i % 2 == 0 { abort() }
+        }
+        i += 1;
+    }
+
+    // Space for the requested capacity is allocated up front.
+    let mut reserved = HashMap::with_capacity_and_keys(0, 0, 1000);
+    let reserved_capacity = reserved.capacity();
+    if reserved_capacity < 1000 { abort() }
+    i = 0;
+    while i < 1000 {
+        reserved.swap(i, i);
+        i += 1;
+    }
+    if reserved.capacity() != reserved_capacity { abort() }
+
+    let large = m.capacity();
+    m.shrink_to(2000);
+    if m.capacity() != large { abort() }
+    m.retain(|k, _| *k < 100);
+    m.shrink_to_fit();
+    if m.capacity() >= large || m.capacity() < 50 || m.len() != 50 { abort() }
+    if *m.find(&98).get() != 99 { abort() }
+
+    let mut sum = 0;
+    let mut count = 0;
+    for (k, v) in m.drain() {
+        if v != k + 1 { abort() }
+        sum += k;
+        count += 1;
+    }
+    if count != 50 || sum != 2450 || m.len() != 0 || m.find(&0).is_some() { abort() }
+
+    m.swap(1, 1);
+    m.swap(2, 2);
+    {
+        let mut drain = m.drain();
+        if drain.next().is_none() { abort() }
+    }
+    if m.len() != 0 || m.find(&1).is_some() || m.find(&2).is_some() { abort() }
+
+    m.swap(3, 3);
+    m.clear();
+    if m.len() != 0 || m.find(&3).is_some() { abort() }
+    m.shrink_to_fit();
+    if m.capacity() != initial { abort() }
+}
+
+fn test_entry() {
+    let mut m = HashMap::with_capacity_and_keys(0, 0, 0);
+
+    match m.entry(1) {
+        Occupied(_) => abort(),
+        Vacant(entry) => { *entry.set(10) += 1; }
+    }
+    if *m.find(&1).get() != 11 { abort() }
+
+    match m.entry(1) {
+        Occupied(mut entry) => {
+            if *entry.key() != 1 || *entry.get() != 11 { abort() }
+            if entry.set(20) != 11 { abort() }
+            *entry.get_mut() += 1;
+        }
+        Vacant(_) => abort()
+    }
+    if *m.find(&1).get() != 21 { abort() }
+
+    match m.entry(1) {
+        Occupied(entry) => { if entry.take() != 21 { abort() } }
+        Vacant(_) => abort()
+    }
+    if m.len() != 0 { abort() }
+
+    if *m.insert_or_update_with(2, 5, |_, v| *v += 1) != 5 { abort() }
+    m.insert_or_update_with(2, 5, |_, v| *v += 1);
+    if *m.find(&2).get() != 6 { abort() }
+    if *m.find_or_insert_with(3, |k| *k * 3) != 9 { abort() }
+    if *m.find_or_insert(3, 0) != 9 { abort() }
+}
+
+fn test_equiv() {
+    let mut m = HashMap::with_capacity_and_keys(0, 0, 0);
+    let mut key = Vec::new();
+    key.push(1u8);
+    key.push(2u8);
+    m.swap(key, 12);
+
+    let owned: &[u8] = &[1u8, 2u8];
+    let missing: &[u8] = &[2u8, 1u8];
+    if *m.find_equiv(&owned).get() != 12 { abort() }
+    if !m.contains_key_equiv(&owned) { abort() }
+    if m.contains_key_equiv(&missing) { abort() }
+    *m.find_mut_equiv(&owned).get() += 1;
+    if m.pop_equiv(&missing).is_some() { abort() }
+    if m.pop_equiv(&owned).get() != 13 { abort() }
+    if m.len() != 0 { abort() }
+
+    let mut cache = LruCache::new(0, 0, 2);
+    let mut a = Vec::new();
+    a.push(1u8);
+    let mut b = Vec::new();
+    b.push(2u8);
+    cache.put(a, 1);
+    cache.put(b, 2);
+    let first: &[u8] = &[1u8];
+    if *cache.get_equiv(&first).get() != 1 { abort() }
+    let mut c = Vec::new();
+    c.push(3u8);
+    cache.put(c, 3);
+    if cache.get_equiv(&first).is_none() { abort() }
+    let second: &[u8] = &[2u8];
+    if cache.get_equiv(&second).is_some() { abort() }
+}
+
+fn test_linked_hash_map() {
+    let mut m = LinkedHashMap::with_capacity_and_keys(0, 0, 0);
+    let mut i = 0;
+    while i < 100 {
+        if !m.insert(i, i * 2) { abort() }
+        i += 1;
+    }
+
+    // Replacing a value keeps the insertion order.
+    if m.swap(0, 1).get() != 0 { abort() }
+    if *m.get(&0).get() != 1 { abort() }
+    i = 0;
+    for (k, _) in m.iter() {
+        if *k != i { abort() }
+        i += 1;
+    }
+    if i != 100 { abort() }
+    for k in m.keys().invert() {
+        i -= 1;
+        if *k != i { abort() }
+    }
+
+    if !m.move_to_back(&0) || !m.move_to_front(&99) || m.move_to_front(&100) { abort() }
+    match m.front() { Some((k, _)) => if *k != 99 { abort() }, None => abort() }
+    match m.back() { Some((k, _)) => if *k != 0 { abort() }, None => abort() }
+    match m.pop_front() { Some((k, v)) => if k != 99 || v != 198 { abort() }, None => abort() }
+    match m.pop_back() { Some((k, v)) => if k != 0 || v != 1 { abort() }, None => abort() }
+    if m.pop(&50).get() != 100 || m.pop(&50).is_some() { abort() }
+    if m.len() != 97 || m.contains_key(&50) { abort() }
+
+    let mut expected = 1;
+    for v in m.values() {
+        if *v != expected * 2 { abort() }
+        expected += if expected == 49 { 2 } else { 1 };
+    }
+
+    // In access order, lookups move entries to the back.
+    m.set_access_order(true);
+    m.get(&1);
+    *m.get_mut(&2).get() += 1;
+    m.swap(3, 0);
+    if m.find(&4).is_none() { abort() }
+    match m.pop_front() { Some((k, _)) => if k != 4 { abort() }, None => abort() }
+    let mut back = m.iter().invert();
+    match back.next() { Some((k, v)) => if *k != 3 || *v != 0 { abort() }, None => abort() }
+    match back.next() { Some((k, v)) => if *k != 2 || *v != 5 { abort() }, None => abort() }
+    match back.next() { Some((k, _)) => if *k != 1 { abort() }, None => abort() }
+
+    m.clear();
+    if m.len() != 0 || m.iter().next().is_some() || m.pop_front().is_some() { abort() }
+    m.insert(7, 7);
+    match m.front() { Some((k, v)) => if *k != 7 || *v != 7 { abort() }, None => abort() }
+}
+
+fn test_lru_cache() {
+    let mut cache = LruCache::new(0, 0, 2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    if cache.pop(&1).get() != 10 || cache.len() != 1 { abort() }
+    cache.put(3, 30);
+    cache.put(1, 10);
+    if cache.get(&2).is_some() || *cache.get(&3).get() != 30 { abort() }
+    match cache.put(4, 40) { Some((k, v)) => if k != 1 || v != 10 { abort() }, None => abort() }
+    if cache.get(&1).is_some() { abort() }
+    if cache.put(4, 41).is_some() { abort() }
+    cache.change_capacity(1);
+    if cache.len() != 1 || *cache.get(&4).get() != 41 { abort() }
+}
+
 #[start]
 fn main(_: int, _: **u8) -> int {
     test_siphash();
+    test_siphash_1_3();
+    test_siphash_128();
+    test_halfsiphash();
+    test_fnv();
+    test_hasher_map();
+    test_hash_bytes();
+    test_random_keys();
+    test_hash_set();
+    test_hash_map_iter();
+    test_hash_map_pop();
+    test_hash_map_shrink();
+    test_entry();
+    test_equiv();
+    test_linked_hash_map();
+    test_lru_cache();
     0
 }
@@ -0,0 +1,191 @@
+#[no_std];
+
+extern mod core;
+
+use core::container::Container;
+use core::fail::abort;
+use core::iter::{Iterator, DoubleEndedIterator};
+use core::option::{Some, None};
+use core::tree::{TreeMap, TreeSet};
+
+static N: uint = 1000;
+
+// Insert the keys 0 to N in a scrambled order, with values twice the keys
+fn scrambled() -> TreeMap<uint, uint> {
+    let mut m = TreeMap::new();
+    let mut i = 0;
+    while i < N {
+        let k = i * 7919 % N;
+        if !m.insert(k, k * 2) { abort() }
+        i += 1;
+    }
+    m
+}
+
+fn test_empty() {
+    let m = TreeMap::<uint, uint>::new();
+    if m.len() != 0 || !m.is_empty() { abort() }
+    if m.find(&0).is_some() { abort() }
+    if m.first().is_some() || m.last().is_some() { abort() }
+    if m.iter().next().is_some() || m.iter().next_back().is_some() { abort() }
+    if m.lower_bound(&0).next().is_some() { abort() }
+}
+
+fn test_insert_find() {
+    let mut m = scrambled();
+    if m.len() != N { abort() }
+
+    let mut k = 0;
+    while k < N {
+        if *m.find(&k).get() != k * 2 { abort() }
+        k += 1;
+    }
+    if m.find(&N).is_some() { abort() }
+
+    if m.swap(5, 0).get() != 10 { abort() }
+    if m.insert(5, 10) { abort() }
+    *m.find_mut(&5).get() += 1;
+    if *m.find(&5).get() != 11 { abort() }
+    if m.len() != N { abort() }
+}
+
+fn test_pop() {
+    let mut m = scrambled();
+
+    let mut i = 0;
+    while i < N {
+        let k = i * 7919 % N;
+        if k % 3 == 0 {
+            if m.pop(&k).get() != k * 2 { abort() }
+            if m.pop(&k).is_some() { abort() }
+        }
+        i += 1;
+    }
+    if m.len() != N - (N + 2) / 3 { abort() }
+
+    let mut k = 0;
+    while k < N {
+        if (k % 3 == 0) != m.find(&k).is_none() { abort() }
+        k += 1;
+    }
+
+    k = 0;
+    while k < N {
+        m.pop(&k);
+        k += 1;
+    }
+    if m.len() != 0 || m.iter().next().is_some() { abort() }
+}
+
+fn test_iter() {
+    let m = scrambled();
+
+    let mut expected = 0;
+    for (k, v) in m.iter() {
+        if *k != expected || *v != expected * 2 { abort() }
+        expected += 1;
+    }
+    if expected != N { abort() }
+
+    for (k, _) in m.rev_iter() {
+        expected -= 1;
+        if *k != expected { abort() }
+    }
+    if expected != 0 { abort() }
+
+    // Both ends meet in the middle without visiting a key twice.
+    let mut iter = m.iter();
+    let mut front = 0;
+    let mut back = N;
+    loop {
+        match iter.next() {
+            Some((k, _)) => { if *k != front { abort() } front += 1; }
+            None => break
+        }
+        match iter.next_back() {
+            Some((k, _)) => { back -= 1; if *k != back { abort() } }
+            None => break
+        }
+    }
+    if front != back { abort() }
+    if iter.next().is_some() || iter.next_back().is_some() { abort() }
+
+    let (lower, upper) = m.iter().size_hint();
+    if lower != N || upper.get() != N { abort() }
+}
+
+fn test_bounds() {
+    let mut m = TreeMap::new();
+    let mut k = 0;
+    while k < N {
+        m.insert(k * 2, ());
+        k += 1;
+    }
+
+    match m.lower_bound(&10).next() { Some((k, _)) => if *k != 10 { abort() }, None => abort() }
+    match m.lower_bound(&11).next() { Some((k, _)) => if *k != 12 { abort() }, None => abort() }
+    match m.upper_bound(&10).next() { Some((k, _)) => if *k != 12 { abort() }, None => abort() }
+    if m.lower_bound(&(N * 2)).next().is_some() { abort() }
+    if m.upper_bound(&(N * 2 - 2)).next().is_some() { abort() }
+
+    let mut expected = 500;
+    for (k, _) in m.lower_bound(&499) {
+        if *k != expected { abort() }
+        expected += 2;
+    }
+    if expected != N * 2 { abort() }
+
+    // The back of a bounded iterator stops at the bound.
+    let iter = m.upper_bound(&1990);
+    let mut count = 0;
+    for (k, _) in iter.invert() {
+        if *k <= 1990 { abort() }
+        count += 1;
+    }
+    if count != 4 { abort() }
+
+    match m.first() { Some((k, _)) => if *k != 0 { abort() }, None => abort() }
+    match m.last() { Some((k, _)) => if *k != N * 2 - 2 { abort() }, None => abort() }
+}
+
+fn test_set() {
+    let mut s = TreeSet::new();
+    if !s.insert(3) || !s.insert(1) || !s.insert(2) { abort() }
+    if s.insert(2) { abort() }
+    if s.len() != 3 || !s.contains(&2) { abort() }
+    if *s.first().get() != 1 || *s.last().get() != 3 { abort() }
+
+    let mut expected = 3;
+    for x in s.rev_iter() {
+        if *x != expected { abort() }
+        expected -= 1;
+    }
+    if *s.lower_bound(&2).next().get() != 2 { abort() }
+    if *s.upper_bound(&2).next().get() != 3 { abort() }
+
+    if !s.remove(&2) || s.remove(&2) || s.contains(&2) { abort() }
+    s.clear();
+    if s.len() != 0 || s.iter().next().is_some() { abort() }
+}
+
+fn test_destructor() {
+    let mut m = TreeMap::new();
+    let mut k = 0;
+    while k < 100 {
+        m.insert(k, ~k);
+        k += 1;
+    }
+    m.pop(&50);
+}
+
+#[start]
+fn main(_: int, _: **u8) -> int {
+    test_empty();
+    test_insert_find();
+    test_pop();
+    test_iter();
+    test_bounds();
+    test_set();
+    test_destructor();
+    0
+}
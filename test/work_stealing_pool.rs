@@ -0,0 +1,119 @@
+#[no_std];
+
+extern mod core;
+
+use core::clone::Clone;
+use core::concurrent::{Queue, work_deque};
+use core::fail::abort;
+use core::option::{Some, None};
+use core::thread::{WorkStealingPool, submit_local, spawn};
+use core::vec::Vec;
+
+fn test_work_deque() {
+    let (mut worker, stealer) = work_deque::<int>();
+    if worker.pop().is_some() || stealer.steal().is_some() || !worker.is_empty() { abort() }
+
+    // Grow past the initial capacity.
+    let mut i = 0;
+    while i < 100 {
+        worker.push(i);
+        i += 1;
+    }
+    if stealer.steal().get() != 0 || stealer.steal().get() != 1 { abort() }
+    if worker.pop().get() != 99 || worker.pop().get() != 98 { abort() }
+
+    i = 2;
+    while i < 98 {
+        if worker.pop().get() != 97 - (i - 2) { abort() }
+        i += 1;
+    }
+    if worker.pop().is_some() || stealer.steal().is_some() || !stealer.is_empty() { abort() }
+
+    // Every value is taken exactly once by the owner or one of the stealers.
+    let mut thieves = Vec::new();
+    i = 0;
+    while i < 4 {
+        let stealer = stealer.clone();
+        thieves.push(spawn(proc() {
+            let mut sum = 0;
+            let mut misses = 0;
+            while misses < 1000 {
+                match stealer.steal() {
+                    Some(x) => { sum += x; misses = 0 }
+                    None => misses += 1
+                }
+            }
+            sum
+        }));
+        i += 1;
+    }
+    let mut sum = 0;
+    i = 0;
+    while i < 10000 {
+        worker.push(i);
+        if i % 3 == 0 {
+            match worker.pop() {
+                Some(x) => sum += x,
+                None => ()
+            }
+        }
+        i += 1;
+    }
+    loop {
+        match worker.pop() {
+            Some(x) => sum += x,
+            None => break
+        }
+    }
+    loop {
+        match thieves.pop() {
+            Some(thief) => sum += *thief.join(),
+            None => break
+        }
+    }
+    if sum != 9999 * 10000 / 2 { abort() }
+}
+
+// Split the range into subtasks on the local deque, sending the leaves to `results`
+fn split(lo: int, hi: int, results: Queue<int>) {
+    if hi - lo <= 4 {
+        let mut i = lo;
+        while i < hi {
+            results.push(i);
+            i += 1;
+        }
+    } else {
+        let mid = lo + (hi - lo) / 2;
+        let left = results.clone();
+        if submit_local(proc() split(lo, mid, left)).is_some() { abort() }
+        split(mid, hi, results);
+    }
+}
+
+fn test_pool() {
+    if submit_local(proc() {}).is_none() { abort() }
+
+    let results = Queue::new();
+    let pool = WorkStealingPool::new(4);
+    let mut i = 0;
+    while i < 10 {
+        let results = results.clone();
+        pool.submit(proc() split(i * 1000, (i + 1) * 1000, results));
+        i += 1;
+    }
+
+    let mut sum = 0;
+    i = 0;
+    while i < 10000 {
+        sum += results.pop();
+        i += 1;
+    }
+    if sum != 9999 * 10000 / 2 { abort() }
+}
+
+#[start]
+fn main(_: int, _: **u8) -> int {
+    test_work_deque();
+    test_pool();
+    0
+}